@@ -1,4 +1,15 @@
+pub mod browse;
+pub mod daily;
 pub mod doctor;
 pub mod episode;
+pub mod gallery;
+pub mod image_cache;
+pub mod image_policy;
+pub mod interaction;
+pub mod metadata;
 pub mod points;
+pub mod practice;
 pub mod quote;
+pub mod team;
+pub mod tournament;
+pub mod watch;
@@ -0,0 +1,203 @@
+use crate::commands::doctor::doctor_name;
+use crate::commands::episode::{row_to_episode, Episode, EPISODE_COLUMNS};
+use anyhow::Result;
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use serenity::all::{ButtonStyle, CommandInteraction, ComponentInteraction, ResolvedValue};
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+
+const PAGE_SIZE: usize = 10;
+
+// Which episodes a paginated listing shows, encoded in the page buttons
+enum Listing {
+    Season(String, i64),
+    Doctor(i64),
+}
+
+impl Listing {
+    fn key(&self) -> String {
+        match self {
+            Listing::Season(era, n) => format!("season:{}:{}", era, n),
+            Listing::Doctor(n) => format!("doctor:{}", n),
+        }
+    }
+
+    fn heading(&self) -> String {
+        match self {
+            Listing::Season(era, n) if era == "classic" => format!("Classic season {}", n),
+            Listing::Season(_, n) => format!("Season {}", n),
+            Listing::Doctor(n) => format!("{} episodes", doctor_name(*n as u8)),
+        }
+    }
+
+    fn episodes(&self, conn: &Connection) -> Result<Vec<Episode>> {
+        let order = "order by era, season, episode, air_date";
+        let episodes = match self {
+            Listing::Season(era, n) => {
+                let mut stmt = conn.prepare(&format!(
+                    "select {} from episodes where era = ?1 and season = ?2 {}",
+                    EPISODE_COLUMNS, order
+                ))?;
+                let episodes = stmt
+                    .query_map(params![era, n], row_to_episode)?
+                    .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+                episodes
+            }
+            Listing::Doctor(n) => {
+                let mut stmt = conn.prepare(&format!(
+                    "select {} from episodes where doctor = ?1 {}",
+                    EPISODE_COLUMNS, order
+                ))?;
+                let episodes = stmt
+                    .query_map(params![n], row_to_episode)?
+                    .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+                episodes
+            }
+        };
+        Ok(episodes)
+    }
+}
+
+pub fn episode_line(episode: &Episode) -> String {
+    match &episode.air_date {
+        Some(date) => format!(
+            "**{}** {} ({})\n",
+            episode.number(),
+            episode.full_title(),
+            date
+        ),
+        None => format!("**{}** {}\n", episode.number(), episode.full_title()),
+    }
+}
+
+fn listing_page(listing: &Listing, page: usize) -> Result<CreateInteractionResponseMessage> {
+    let conn = Connection::open("episodes.db")?;
+    let episodes = listing.episodes(&conn)?;
+    if episodes.is_empty() {
+        return Ok(CreateInteractionResponseMessage::new()
+            .content(format!("No episodes found for {}", listing.heading()))
+            .components(Vec::new()));
+    }
+    let pages = episodes.len().div_ceil(PAGE_SIZE);
+    let page = page.min(pages - 1);
+    let mut msg_content = format!("## {} (page {}/{})\n", listing.heading(), page + 1, pages);
+    for episode in episodes.iter().skip(page * PAGE_SIZE).take(PAGE_SIZE) {
+        msg_content.push_str(&episode_line(episode));
+    }
+    let buttons = vec![
+        CreateButton::new(format!(
+            "browse:{}:{}",
+            listing.key(),
+            page.saturating_sub(1)
+        ))
+        .label("◀ Previous")
+        .style(ButtonStyle::Secondary)
+        .disabled(page == 0),
+        CreateButton::new(format!("browse:{}:{}", listing.key(), page + 1))
+            .label("Next ▶")
+            .style(ButtonStyle::Secondary)
+            .disabled(page + 1 >= pages),
+    ];
+    Ok(CreateInteractionResponseMessage::new()
+        .content(msg_content)
+        .components(vec![CreateActionRow::Buttons(buttons)]))
+}
+
+fn integer_option(cmd: &CommandInteraction, name: &str) -> Option<i64> {
+    cmd.data
+        .options()
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| match o.value {
+            ResolvedValue::Integer(n) => Some(n),
+            _ => None,
+        })
+}
+
+// Handles /season n [era], the classic and revived series both number their seasons from 1
+pub fn season_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let season = integer_option(cmd, "n").ok_or(anyhow::anyhow!("Failed to parse season"))?;
+    let era = cmd
+        .data
+        .options()
+        .iter()
+        .find(|o| o.name == "era")
+        .and_then(|o| match o.value {
+            ResolvedValue::String(s) => Some(s.to_string()),
+            _ => None,
+        })
+        .unwrap_or(String::from("revival"));
+    Ok(CreateInteractionResponse::Message(listing_page(
+        &Listing::Season(era, season),
+        0,
+    )?))
+}
+
+// Handles /episodes doctor:n
+pub fn episodes_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let doctor = integer_option(cmd, "doctor").ok_or(anyhow::anyhow!("Failed to parse doctor"))?;
+    Ok(CreateInteractionResponse::Message(listing_page(
+        &Listing::Doctor(doctor),
+        0,
+    )?))
+}
+
+// Handles the previous/next buttons of a listing
+pub fn browse_button_response(
+    component: &ComponentInteraction,
+) -> Result<CreateInteractionResponse> {
+    let parts: Vec<&str> = component.data.custom_id.split(':').collect();
+    let (listing, page) = match parts[..] {
+        [_, "season", era, value, page] => (
+            Listing::Season(era.to_string(), value.parse::<i64>()?),
+            page,
+        ),
+        [_, "doctor", value, page] => (Listing::Doctor(value.parse::<i64>()?), page),
+        _ => return Err(anyhow::anyhow!("Failed to parse browse button")),
+    };
+    let page = page.parse::<usize>()?;
+    Ok(CreateInteractionResponse::UpdateMessage(listing_page(
+        &listing, page,
+    )?))
+}
+
+// Handles /onthisday, episodes that first aired on today's date in any year. Revival air
+// dates come from the metadata cache, so the answer says when some are still unknown.
+pub fn onthisday_cmd_response() -> Result<CreateInteractionResponse> {
+    let today = Utc::now().date_naive();
+    let month_day = today.format("%m-%d").to_string();
+    let conn = Connection::open("episodes.db")?;
+    let mut stmt = conn.prepare(&format!(
+        "select {} from episodes where substr(air_date, 6, 5) = ?1 order by air_date",
+        EPISODE_COLUMNS
+    ))?;
+    let episodes = stmt
+        .query_map(params![month_day], row_to_episode)?
+        .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+    let undated: i64 = conn.query_row(
+        "select count(*) from episodes where air_date is null",
+        [],
+        |row| row.get(0),
+    )?;
+    let heading = format!("## On this day, {}", today.format("%B %-d"));
+    let mut msg_content = if episodes.is_empty() {
+        format!("{}\nNo episodes first aired on this day\n", heading)
+    } else {
+        let mut msg_content = format!("{}\n", heading);
+        for episode in &episodes {
+            msg_content.push_str(&episode_line(episode));
+        }
+        msg_content
+    };
+    if undated > 0 {
+        msg_content.push_str(&format!(
+            "-# {} episodes have no known air date yet, the bot owner can fill them in with `cargo run -- prefetch`",
+            undated
+        ));
+    }
+    Ok(CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new().content(msg_content),
+    ))
+}
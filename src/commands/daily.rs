@@ -0,0 +1,245 @@
+use crate::commands::interaction::ephemeral;
+use crate::commands::points::find_trivia_channel;
+use crate::content::loaded_content;
+use anyhow::Result;
+use chrono::{Days, NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::all::{ButtonStyle, CommandInteraction, ComponentInteraction, GuildId};
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use serenity::prelude::*;
+
+const CHOICES: [&str; 4] = ["a", "b", "c", "d"];
+
+struct DailyQuestion {
+    question: String,
+    answer: String,
+}
+
+fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+// Picks the question of the day, the first pick of a day is stored so it never changes
+fn daily_question(conn: &Connection, day: &str) -> Result<DailyQuestion> {
+    let stored = conn
+        .query_row(
+            "select question, answer from daily_questions where day = ?1",
+            params![day],
+            |row| {
+                Ok(DailyQuestion {
+                    question: row.get(0)?,
+                    answer: row.get(1)?,
+                })
+            },
+        )
+        .optional()?;
+    if let Some(body) = stored {
+        return Ok(body);
+    }
+    let questions = &loaded_content().questions;
+    if questions.is_empty() {
+        return Err(anyhow::anyhow!("questions.json has no questions"));
+    }
+    // Every user gets the same question, cycling through the file one day at a time
+    let date: NaiveDate = day.parse()?;
+    let days = date.signed_duration_since(NaiveDate::default()).num_days();
+    let question = &questions[days as usize % questions.len()];
+    conn.execute(
+        "insert or ignore into daily_questions (day, question, answer) values (?1, ?2, ?3)",
+        params![day, question.question, question.answer],
+    )?;
+    Ok(DailyQuestion {
+        question: question.question.clone(),
+        answer: question.answer.clone(),
+    })
+}
+
+fn get_streak(conn: &Connection, guild: &str, user: &str) -> Result<i64> {
+    let streak: Option<i64> = conn
+        .query_row(
+            "select streak from daily_streaks where guild = ?1 and user = ?2",
+            params![guild, user],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(streak.unwrap_or(0))
+}
+
+pub fn daily_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let guild = match cmd.guild_id {
+        Some(body) => body.to_string(),
+        None => return Ok(ephemeral("Command was not run in guild")),
+    };
+    let user = cmd.user.id.to_string();
+    let day = today().to_string();
+    let conn = Connection::open("points.db")?;
+    let answered: Option<i64> = conn
+        .query_row(
+            "select correct from daily_answers where guild = ?1 and user = ?2 and day = ?3",
+            params![guild, user, day],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if answered.is_some() {
+        let streak = get_streak(&conn, &guild, &user)?;
+        return Ok(ephemeral(format!(
+            "You already answered today's question, come back tomorrow! Current streak: {}",
+            streak
+        )));
+    }
+    let question = daily_question(&conn, &day)?;
+    let buttons = CHOICES
+        .iter()
+        .map(|choice| {
+            CreateButton::new(format!("daily:{}:{}", day, choice))
+                .label(choice.to_ascii_uppercase())
+                .style(ButtonStyle::Primary)
+        })
+        .collect();
+    let rsp = CreateInteractionResponseMessage::new()
+        .content(format!(
+            "## Daily challenge {}\n{}",
+            day,
+            question.question.replace("**", "")
+        ))
+        .components(vec![CreateActionRow::Buttons(buttons)])
+        .ephemeral(true);
+    Ok(CreateInteractionResponse::Message(rsp))
+}
+
+// Handles the answer buttons of the daily question
+pub fn daily_button_response(
+    component: &ComponentInteraction,
+) -> Result<CreateInteractionResponse> {
+    let parts: Vec<&str> = component.data.custom_id.split(':').collect();
+    let (day, choice) = match parts[..] {
+        [_, day, choice] => (day, choice),
+        _ => return Err(anyhow::anyhow!("Failed to parse daily button")),
+    };
+    let guild = match component.guild_id {
+        Some(body) => body.to_string(),
+        None => return Ok(ephemeral("Command was not run in guild")),
+    };
+    let update = |content: String| {
+        CreateInteractionResponse::UpdateMessage(
+            CreateInteractionResponseMessage::new()
+                .content(content)
+                .components(Vec::new()),
+        )
+    };
+    let now = today();
+    if day != now.to_string() {
+        return Ok(update(String::from(
+            "This daily question has expired, use `/daily` for today's one",
+        )));
+    }
+    let user = component.user.id.to_string();
+    let conn = Connection::open("points.db")?;
+    let question = daily_question(&conn, day)?;
+    let correct = question.answer == choice;
+    let inserted = conn.execute(
+        "insert or ignore into daily_answers (guild, user, day, correct) values (?1, ?2, ?3, ?4)",
+        params![guild, user, day, correct],
+    )?;
+    if inserted == 0 {
+        return Ok(update(String::from(
+            "You already answered today's question",
+        )));
+    }
+
+    // A streak continues only if yesterday's question was answered correctly
+    let yesterday = now
+        .checked_sub_days(Days::new(1))
+        .map(|d| d.to_string())
+        .unwrap_or_default();
+    let last_day: Option<String> = conn
+        .query_row(
+            "select last_day from daily_streaks where guild = ?1 and user = ?2",
+            params![guild, user],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let previous = get_streak(&conn, &guild, &user)?;
+    let streak = match (correct, last_day) {
+        (false, _) => 0,
+        (true, Some(last)) if last == yesterday => previous + 1,
+        (true, _) => 1,
+    };
+    conn.execute(
+        "insert into daily_streaks (guild, user, streak, best, last_day) values (?1, ?2, ?3, ?3, ?4)
+        on conflict(guild, user) do update set streak = excluded.streak,
+            best = max(best, excluded.streak), last_day = excluded.last_day",
+        params![guild, user, streak, day],
+    )?;
+    let content = if correct {
+        format!("✅ Correct! Your daily streak is now {}", streak)
+    } else {
+        String::from("❌ Not quite, your streak was reset. The answer is revealed tomorrow in the trivia channel")
+    };
+    Ok(update(content))
+}
+
+// Posts the results of every finished daily question that hasn't been summarized yet. A
+// guild that fails is retried on the next run and doesn't hold up the others.
+pub async fn post_daily_summaries(guilds: Vec<GuildId>, ctx: &Context) -> Result<()> {
+    let today = today().to_string();
+    for guild in guilds {
+        match post_guild_summaries(guild, &today, ctx).await {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Failed to post daily summaries in {}: {}", guild, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn post_guild_summaries(guild: GuildId, today: &str, ctx: &Context) -> Result<()> {
+    let pending: Vec<String> = {
+        let conn = Connection::open("points.db")?;
+        let mut stmt = conn.prepare(
+            "select distinct day from daily_answers where guild = ?1 and day < ?2
+            and day not in (select day from daily_summaries where guild = ?1) order by day",
+        )?;
+        let days = stmt
+            .query_map(params![guild.to_string(), today], |row| row.get(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+        days
+    };
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let channel = match find_trivia_channel(guild, ctx).await? {
+        Some(body) => body,
+        None => return Ok(()),
+    };
+    for day in pending {
+        let content = {
+            let conn = Connection::open("points.db")?;
+            let question = daily_question(&conn, &day)?;
+            let (total, correct): (i64, i64) = conn.query_row(
+                "select count(*), coalesce(sum(correct), 0) from daily_answers where guild = ?1 and day = ?2",
+                params![guild.to_string(), day],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            let percent = if total == 0 { 0 } else { correct * 100 / total };
+            format!(
+                "## Daily challenge {} results\n{}\nThe answer was **{}**, {}% of {} players got it right",
+                day,
+                question.question.replace("**", ""),
+                question.answer,
+                percent,
+                total
+            )
+        };
+        channel.say(&ctx.http, content).await?;
+        let conn = Connection::open("points.db")?;
+        conn.execute(
+            "insert or ignore into daily_summaries (guild, day) values (?1, ?2)",
+            params![guild.to_string(), day],
+        )?;
+    }
+    Ok(())
+}
@@ -1,120 +1,580 @@
-use serde_json::Value;
-use serenity::builder::{CreateAttachment, EditInteractionResponse};
+use crate::commands::episode::{doctor_companions, row_to_episode, Episode, EPISODE_COLUMNS};
+use crate::commands::gallery::{gallery_entry, GalleryEntry};
+use crate::commands::image_cache::{block_cached_image, cached_image, fetch_cached, CachedImage};
+use crate::commands::image_policy::safesearch;
+use crate::commands::interaction::{ephemeral, has_permission};
+use crate::commands::quote::quote_for_doctor;
+use crate::http::{http_client, HttpClient};
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+use serenity::all::{
+    ButtonStyle, CommandInteraction, ComponentInteraction, GuildId, Permissions, ResolvedValue,
+};
+use serenity::builder::{
+    CreateActionRow, CreateAttachment, CreateAutocompleteResponse, CreateButton, CreateEmbed,
+    CreateEmbedFooter, CreateInteractionResponse, CreateSelectMenu, CreateSelectMenuKind,
+    CreateSelectMenuOption, EditAttachments, EditInteractionResponse,
+};
 use serenity::prelude::*;
 use std::env;
-use ureq::json;
-
-const NUMB_AS_STR: [&str; 14] = [
-    "first",
-    "second",
-    "third",
-    "fourth",
-    "fifth",
-    "sixth",
-    "seventh",
-    "eigth",
-    "ninth",
-    "tenth",
-    "eleventh",
-    "twelfth",
-    "thirteenth",
-    "fourteenth",
+
+// An incarnation of the Doctor. Numbered Doctors have an ordinal, which is what the
+// episode catalog stores; the War and Fugitive Doctors sit outside the numbering.
+pub struct Doctor {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub ordinal: Option<u8>,
+    pub aliases: &'static [&'static str],
+}
+
+// Every Doctor in story order, previous/next in the profile follow this order
+pub const DOCTORS: [Doctor; 17] = [
+    Doctor {
+        id: "fugitive",
+        name: "Fugitive Doctor",
+        ordinal: None,
+        aliases: &["ruth", "jo martin"],
+    },
+    Doctor {
+        id: "first",
+        name: "First Doctor",
+        ordinal: Some(1),
+        aliases: &["1st", "hartnell"],
+    },
+    Doctor {
+        id: "second",
+        name: "Second Doctor",
+        ordinal: Some(2),
+        aliases: &["2nd", "troughton"],
+    },
+    Doctor {
+        id: "third",
+        name: "Third Doctor",
+        ordinal: Some(3),
+        aliases: &["3rd", "pertwee"],
+    },
+    Doctor {
+        id: "fourth",
+        name: "Fourth Doctor",
+        ordinal: Some(4),
+        aliases: &["4th", "tom baker"],
+    },
+    Doctor {
+        id: "fifth",
+        name: "Fifth Doctor",
+        ordinal: Some(5),
+        aliases: &["5th", "davison"],
+    },
+    Doctor {
+        id: "sixth",
+        name: "Sixth Doctor",
+        ordinal: Some(6),
+        aliases: &["6th", "colin baker"],
+    },
+    Doctor {
+        id: "seventh",
+        name: "Seventh Doctor",
+        ordinal: Some(7),
+        aliases: &["7th", "mccoy"],
+    },
+    Doctor {
+        id: "eighth",
+        name: "Eighth Doctor",
+        ordinal: Some(8),
+        aliases: &["8th", "mcgann"],
+    },
+    Doctor {
+        id: "war",
+        name: "War Doctor",
+        ordinal: None,
+        aliases: &["john hurt", "hurt"],
+    },
+    Doctor {
+        id: "ninth",
+        name: "Ninth Doctor",
+        ordinal: Some(9),
+        aliases: &["9th", "eccleston"],
+    },
+    Doctor {
+        id: "tenth",
+        name: "Tenth Doctor",
+        ordinal: Some(10),
+        aliases: &["10th"],
+    },
+    Doctor {
+        id: "eleventh",
+        name: "Eleventh Doctor",
+        ordinal: Some(11),
+        aliases: &["11th", "matt smith"],
+    },
+    Doctor {
+        id: "twelfth",
+        name: "Twelfth Doctor",
+        ordinal: Some(12),
+        aliases: &["12th", "capaldi"],
+    },
+    Doctor {
+        id: "thirteenth",
+        name: "Thirteenth Doctor",
+        ordinal: Some(13),
+        aliases: &["13th", "whittaker"],
+    },
+    Doctor {
+        id: "fourteenth",
+        name: "Fourteenth Doctor",
+        ordinal: Some(14),
+        aliases: &["14th"],
+    },
+    Doctor {
+        id: "fifteenth",
+        name: "Fifteenth Doctor",
+        ordinal: Some(15),
+        aliases: &["15th", "gatwa", "ncuti"],
+    },
 ];
 
-pub async fn doctor_cmd_response(option: u8, ctx: &Context) -> EditInteractionResponse {
-    // API key
-    let api_key: String = match env::var("RAPID_API") {
+// Display name of the n-th Doctor, e.g. "Ninth Doctor"
+pub fn doctor_name(n: u8) -> String {
+    match DOCTORS.iter().find(|d| d.ordinal == Some(n)) {
+        Some(doctor) => doctor.name.to_string(),
+        None => format!("Doctor {}", n),
+    }
+}
+
+// Finds a Doctor by ID, number, name or alias, e.g. "war", "15", "the tenth doctor"
+pub fn find_doctor(text: &str) -> Option<&'static Doctor> {
+    let text = text.trim().to_lowercase();
+    let text = text.strip_prefix("the ").unwrap_or(&text);
+    let text = text.strip_suffix(" doctor").unwrap_or(text).trim();
+    DOCTORS.iter().find(|d| {
+        d.id == text
+            || d.name.eq_ignore_ascii_case(text)
+            || d.aliases.contains(&text)
+            || d.ordinal.map(|n| n.to_string()) == Some(text.to_string())
+    })
+}
+
+// Discord shows at most 25 autocomplete choices, there are fewer Doctors than that
+pub fn doctor_autocomplete(cmd: &CommandInteraction) -> CreateAutocompleteResponse {
+    let typed = match cmd.data.autocomplete() {
+        Some(option) => option.value.trim().to_lowercase(),
+        None => String::new(),
+    };
+    let mut response = CreateAutocompleteResponse::new();
+    for doctor in DOCTORS.iter().filter(|d| {
+        typed.is_empty()
+            || d.name.to_lowercase().contains(&typed)
+            || d.aliases.iter().any(|a| a.contains(typed.as_str()))
+            || d.ordinal.map(|n| n.to_string()) == Some(typed.clone())
+    }) {
+        response = response.add_string_choice(doctor.name, doctor.id);
+    }
+    response
+}
+
+// Image search is only a fallback for Doctors without a gallery picture, enabled with
+// DOCTOR_IMAGE_SEARCH=true
+fn image_search_enabled() -> bool {
+    env::var("DOCTOR_IMAGE_SEARCH")
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+// Episode count, first and last episode of a Doctor in the catalog
+fn doctor_episodes(doctor: u8) -> Result<(i64, Option<Episode>, Option<Episode>)> {
+    let conn = Connection::open("episodes.db")?;
+    let count: i64 = conn.query_row(
+        "select count(*) from episodes where doctor = ?1",
+        params![doctor],
+        |row| row.get(0),
+    )?;
+    // The classic era sorts before the revival, as it aired
+    let first = conn
+        .query_row(
+            &format!(
+                "select {} from episodes where doctor = ?1 order by era, season, episode, part limit 1",
+                EPISODE_COLUMNS
+            ),
+            params![doctor],
+            row_to_episode,
+        )
+        .optional()?;
+    let last = conn
+        .query_row(
+            &format!(
+                "select {} from episodes where doctor = ?1
+                order by era desc, season desc, episode desc, part desc limit 1",
+                EPISODE_COLUMNS
+            ),
+            params![doctor],
+            row_to_episode,
+        )
+        .optional()?;
+    Ok((count, first, last))
+}
+
+// Picture of a Doctor, from the gallery or the image search fallback, with its credit
+async fn doctor_image(
+    doctor: &Doctor,
+    entry: Option<&GalleryEntry>,
+    ctx: &Context,
+) -> Option<(CreateAttachment, String)> {
+    if let Some((image, path)) = entry.and_then(|entry| entry.pick_image()) {
+        match CreateAttachment::path(&path).await {
+            Ok(body) => return Some((body, image.credit.clone())),
+            Err(e) => {
+                println!("Failed to read {}: {}", path.display(), e);
+            }
+        }
+    }
+    if !image_search_enabled() {
+        return None;
+    }
+    let http = match http_client(ctx).await {
+        Ok(body) => body,
+        Err(e) => {
+            println!("Failed to get HTTP client: {}", e);
+            return None;
+        }
+    };
+    let image = match searched_image(doctor, &http, false).await {
+        Ok(Some(body)) => body,
+        Ok(None) => return None,
+        Err(e) => {
+            println!("Failed to search a picture of the {}: {}", doctor.name, e);
+            return None;
+        }
+    };
+    match CreateAttachment::path(image.path()).await {
+        Ok(body) => Some((body, String::from("image search result"))),
+        Err(e) => {
+            println!("Failed to read {}: {}", image.path().display(), e);
+            None
+        }
+    }
+}
+
+fn episode_field(episode: &Option<Episode>) -> String {
+    match episode {
+        Some(episode) => format!("{} ({})", episode.full_title(), episode.number()),
+        None => String::from("Not in the catalog"),
+    }
+}
+
+// Profile card of a Doctor with buttons to step through the Doctors
+pub async fn doctor_cmd_response(
+    option: &str,
+    guild: Option<GuildId>,
+    ctx: &Context,
+) -> EditInteractionResponse {
+    let doctor = match find_doctor(option) {
+        Some(body) => body,
+        None => {
+            return EditInteractionResponse::new()
+                .content(format!("There is no Doctor called {}", option))
+        }
+    };
+    let name = doctor.name;
+    let entry = match gallery_entry(doctor.id) {
+        Ok(body) => body,
+        Err(e) => {
+            println!("Failed to read the Doctor gallery: {}", e);
+            None
+        }
+    };
+    // Only numbered Doctors are tracked in the episode catalog
+    let episodes = match doctor.ordinal {
+        Some(n) => doctor_episodes(n),
+        None => Ok((0, None, None)),
+    };
+    let (count, first, last) = match episodes {
+        Ok(body) => body,
+        Err(e) => {
+            println!("Failed to read episodes of the {}: {}", name, e);
+            (0, None, None)
+        }
+    };
+    let quote = match quote_for_doctor(doctor.id, guild.map(|g| g.to_string()).as_deref()) {
         Ok(body) => body,
-        Err(_) => return EditInteractionResponse::new().content("Failed to get API key"),
+        Err(e) => {
+            println!("Failed to read quotes: {}", e);
+            None
+        }
+    };
+
+    let mut embed = CreateEmbed::new().title(name);
+    if let Some(quote) = &quote {
+        embed = embed.description(format!("*“{}”*", quote.quote));
+    }
+    if let Some(entry) = &entry {
+        embed = embed
+            .field("Played by", &entry.actor, true)
+            .field("Years", &entry.era, true);
+    }
+    if doctor.ordinal.is_some() {
+        embed = embed
+            .field("Episodes", count.to_string(), true)
+            .field("First episode", episode_field(&first), true)
+            .field("Last episode", episode_field(&last), true);
+    }
+    let companions = doctor_companions(doctor);
+    if !companions.is_empty() {
+        embed = embed.field("Companions", companions.join(", "), false);
+    }
+
+    let mut attachments = EditAttachments::new();
+    match doctor_image(doctor, entry.as_ref(), ctx).await {
+        Some((attachment, credit)) => {
+            embed = embed
+                .image(format!("attachment://{}", attachment.filename))
+                .footer(CreateEmbedFooter::new(format!("Image: {}", credit)));
+            attachments = attachments.add(attachment);
+        }
+        None => {
+            embed = embed.footer(CreateEmbedFooter::new("No picture in the gallery yet"));
+        }
+    }
+
+    let index = DOCTORS.iter().position(|d| d.id == doctor.id).unwrap_or(0);
+    let previous = DOCTORS[index.saturating_sub(1)].id;
+    let next = DOCTORS[(index + 1).min(DOCTORS.len() - 1)].id;
+    let mut components = vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("doctor:{}", previous))
+            .label("◀ Previous Doctor")
+            .style(ButtonStyle::Secondary)
+            .disabled(index == 0),
+        CreateButton::new(format!("doctor:{}", next))
+            .label("Next Doctor ▶")
+            .style(ButtonStyle::Secondary)
+            .disabled(index + 1 == DOCTORS.len()),
+    ])];
+    // First and last episode open in the episode details view, as a reply of their own
+    let mut picks: Vec<&Episode> = first.iter().collect();
+    if let Some(episode) = &last {
+        if !picks.iter().any(|e| e.id == episode.id) {
+            picks.push(episode);
+        }
+    }
+    let episodes: Vec<CreateSelectMenuOption> = picks
+        .iter()
+        .map(|e| {
+            CreateSelectMenuOption::new(
+                format!("{} ({})", e.full_title(), e.number()),
+                e.id.clone(),
+            )
+        })
+        .collect();
+    if !episodes.is_empty() {
+        let menu = CreateSelectMenu::new(
+            "doctor_episode",
+            CreateSelectMenuKind::String { options: episodes },
+        )
+        .placeholder("See the first or last episode");
+        components.push(CreateActionRow::SelectMenu(menu));
+    }
+
+    EditInteractionResponse::new()
+        .content("")
+        .embed(embed)
+        .components(components)
+        .attachments(attachments)
+}
+
+// Handles the previous/next Doctor buttons
+pub async fn doctor_button_response(
+    component: &ComponentInteraction,
+    ctx: &Context,
+) -> Result<EditInteractionResponse> {
+    let id = match component.data.custom_id.split(':').nth(1) {
+        Some(body) => body,
+        None => return Err(anyhow::anyhow!("Failed to parse doctor button")),
     };
+    Ok(doctor_cmd_response(id, component.guild_id, ctx).await)
+}
+
+// Handles /blockimage doctor [reason], moderators block the cached search picture of a
+// Doctor and the next /doctor searches for another one
+pub fn blockimage_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    if !has_permission(cmd.member.as_deref(), Permissions::MANAGE_MESSAGES) {
+        return Ok(ephemeral(
+            "Only members with Manage Messages can block pictures",
+        ));
+    }
+    let mut name = String::new();
+    let mut reason = String::from("blocked by a moderator");
+    for option in cmd.data.options() {
+        match (option.name, option.value) {
+            ("doctor", ResolvedValue::String(s)) => name = s.to_string(),
+            ("reason", ResolvedValue::String(s)) => reason = s.to_string(),
+            _ => {}
+        }
+    }
+    let doctor = match find_doctor(&name) {
+        Some(body) => body,
+        None => return Ok(ephemeral(format!("There is no Doctor called {}", name))),
+    };
+    let conn = Connection::open("episodes.db")?;
+    match block_cached_image(&conn, doctor.id, &reason, &cmd.user.id.to_string())? {
+        Some(image) => Ok(ephemeral(format!(
+            "Blocked the cached picture of the {} ({}), it won't be posted again",
+            doctor.name,
+            &image.hash[..12]
+        ))),
+        None => Ok(ephemeral(format!(
+            "There is no cached search picture of the {}",
+            doctor.name
+        ))),
+    }
+}
+
+// Results searched per Doctor, later ones are tried when earlier ones fail the image policy
+const SEARCH_RESULTS: usize = 5;
+
+// Looks the Doctor up with the RapidAPI Google image search
+async fn search_image_urls(doctor: &Doctor, http: &HttpClient) -> Result<Vec<String>> {
+    // API key
+    let api_key: String = env::var("RAPID_API")?;
 
     // Preparing search string
     let mut to_search = String::from("doctor who ");
 
-    to_search.push_str(NUMB_AS_STR[(option - 1) as usize]);
-    to_search.push_str(" doctor");
-
-    //println!("{}", to_search);
+    to_search.push_str(&doctor.name.to_lowercase());
 
     // Payload for the http POST request
     let payload = json!({
         "text": &to_search,
-        "safesearch": "off",
+        "safesearch": safesearch(),
         "region": "wt-wt",
         "color": "",
         "size": "",
         "type_image": "",
         "layout": "",
-        "max_results": 1
+        "max_results": SEARCH_RESULTS
     });
 
     // Preparing http POST request
-    let http_req = ureq::post("https://google-api31.p.rapidapi.com/imagesearch")
-        .set("content-type", "application/json")
-        .set("X-RapidAPI-Key", &api_key)
-        .set("X-RapidAPI-Host", "google-api31.p.rapidapi.com")
-        .send_json(payload);
-
-    let api_response = match http_req {
-        Ok(body) => Some(body.into_string().unwrap_or(String::from(""))),
-        Err(_) => None,
+    let http_req = http
+        .post("https://google-api31.p.rapidapi.com/imagesearch")
+        .header("X-RapidAPI-Key", &api_key)
+        .header("X-RapidAPI-Host", "google-api31.p.rapidapi.com")
+        .json(&payload);
+
+    let api_response: Option<Value> = http.quota_json(http_req).await?;
+    let urls = match api_response {
+        Some(resp_json) => resp_json["result"]
+            .as_array()
+            .map(|results| {
+                results
+                    .iter()
+                    .filter_map(|result| result["image"].as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        None => Vec::new(),
     };
+    Ok(urls)
+}
 
-    let url: &str;
-    let resp_json: Value;
-    let mut msg_content = format!(
-        "Here's a picture of the {} doctor",
-        NUMB_AS_STR[(option - 1) as usize]
-    );
-
-    if let Some(body) = api_response {
-        let ok_parse: Result<Value, serde_json::Error> = serde_json::from_str(&body);
-        resp_json = match ok_parse {
-            Ok(body) => body,
-            Err(_) => {
-                msg_content = format!(
-                    "No image found for the {} doctor",
-                    NUMB_AS_STR[(option - 1) as usize]
-                );
-                return EditInteractionResponse::new().content(msg_content);
-            }
-        };
-        let ok: Option<&str> = resp_json["result"][0]["image"].as_str();
-        url = match ok {
-            Some(body) => body,
-            None => {
-                // In caz ca API-ul merge, dar nu gasim nimic
-                msg_content = format!(
-                    "No image found for the {} doctor",
-                    NUMB_AS_STR[(option - 1) as usize]
-                );
-                "https://upload.wikimedia.org/wikipedia/commons/thumb/d/d1/Image_not_available.png/640px-Image_not_available.png"
+// Picture from the image search, served from the on-disk cache once it was fetched
+pub async fn searched_image(
+    doctor: &Doctor,
+    http: &HttpClient,
+    refresh: bool,
+) -> Result<Option<CachedImage>> {
+    if !refresh {
+        if let Some(image) = cached_image(&Connection::open("episodes.db")?, doctor.id)? {
+            return Ok(Some(image));
+        }
+    }
+    // The first result that passes the image policy wins
+    for url in search_image_urls(doctor, http).await? {
+        match fetch_cached(http, doctor.id, &url).await {
+            Ok(Some(image)) => return Ok(Some(image)),
+            Ok(None) => {}
+            Err(e) => {
+                println!("Skipped picture {} of the {}: {}", url, doctor.name, e);
             }
-        };
-    } else {
-        // In caz ca nu merge API-ul
-        msg_content = format!(
-            "No image found for the {} doctor",
-            NUMB_AS_STR[(option - 1) as usize]
-        );
-        url = "https://upload.wikimedia.org/wikipedia/commons/thumb/d/d1/Image_not_available.png/640px-Image_not_available.png"
+        }
     }
+    Ok(None)
+}
 
-    //println!("{}", url);
-    let img_req = CreateAttachment::url(&ctx.http, url).await;
-
-    let mut rsp = EditInteractionResponse::new().content(msg_content);
-    match img_req {
-        Ok(body) => {
-            rsp = rsp.new_attachment(body);
+// Fills the image cache for every Doctor without a gallery picture, used by the
+// warm-cache subcommand
+pub async fn warm_image_cache(http: &HttpClient, force: bool) -> Result<()> {
+    if env::var("RAPID_API").is_err() {
+        return Err(anyhow::anyhow!(
+            "RAPID_API isn't set, the image search needs it"
+        ));
+    }
+    let mut cached = 0;
+    for doctor in DOCTORS.iter() {
+        let entry = gallery_entry(doctor.id).unwrap_or(None);
+        if entry
+            .as_ref()
+            .and_then(|entry| entry.pick_image())
+            .is_some()
+        {
+            continue;
         }
-        Err(_) => {
-            msg_content = format!(
-                "No image found for the {} doctor",
-                NUMB_AS_STR[(option - 1) as usize]
-            );
-            rsp = rsp.content(msg_content);
+        match searched_image(doctor, http, force).await {
+            Ok(Some(image)) => {
+                println!("✅{}: {} ({} bytes)", doctor.name, image.file, image.size);
+                cached += 1;
+            }
+            Ok(None) => println!("No picture found for the {}", doctor.name),
+            Err(e) => println!("❌Failed to cache a picture of the {}: {}", doctor.name, e),
         }
+        // Stay well under the API rate limit
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    }
+    println!("✅{} Doctor pictures in the image cache", cached);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(text: &str) -> Option<&'static str> {
+        find_doctor(text).map(|d| d.id)
+    }
+
+    #[test]
+    fn finds_doctors_by_id_number_and_name() {
+        assert_eq!(found("tenth"), Some("tenth"));
+        assert_eq!(found("10"), Some("tenth"));
+        assert_eq!(found("Tenth Doctor"), Some("tenth"));
+        assert_eq!(found("  The Tenth Doctor "), Some("tenth"));
+    }
+
+    #[test]
+    fn finds_doctors_by_alias() {
+        assert_eq!(found("13th"), Some("thirteenth"));
+        assert_eq!(found("Whittaker"), Some("thirteenth"));
+        assert_eq!(found("ncuti"), Some("fifteenth"));
+        assert_eq!(found("jo martin"), Some("fugitive"));
+    }
+
+    #[test]
+    fn finds_doctors_outside_the_numbering() {
+        assert_eq!(found("war"), Some("war"));
+        assert_eq!(found("the war doctor"), Some("war"));
+        assert_eq!(found("Fugitive Doctor"), Some("fugitive"));
+    }
+
+    #[test]
+    fn unknown_doctors_are_not_found() {
+        assert_eq!(found(""), None);
+        assert_eq!(found("16"), None);
+        assert_eq!(found("the master"), None);
+    }
+
+    #[test]
+    fn doctor_names_follow_the_ordinal() {
+        assert_eq!(doctor_name(8), "Eighth Doctor");
+        assert_eq!(doctor_name(15), "Fifteenth Doctor");
+        assert_eq!(doctor_name(20), "Doctor 20");
     }
-    rsp
 }
@@ -1,9 +1,15 @@
+use crate::commands::doctor::{doctor_name, Doctor};
+use crate::commands::metadata::{metadata_providers, title_metadata, TitleMetadata};
+use crate::http::HttpClient;
 use anyhow::Result;
-use rusqlite::{Connection, Statement};
+use futures::future::join_all;
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use serenity::builder::EditInteractionResponse;
-use std::env;
+use serenity::all::{CommandInteraction, ComponentInteraction, ComponentInteractionDataKind};
+use serenity::builder::{
+    CreateActionRow, CreateAutocompleteResponse, CreateEmbed, CreateSelectMenu,
+    CreateSelectMenuKind, CreateSelectMenuOption, EditInteractionResponse,
+};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Episode {
@@ -11,71 +17,497 @@ pub struct Episode {
     pub title: String,
     pub season: i32,
     pub episode: i32,
+    #[serde(default = "default_era")]
+    pub era: String,
+    #[serde(default)]
+    pub doctor: Option<u8>,
+    #[serde(default)]
+    pub air_date: Option<String>,
+    // Production code of a classic serial, e.g. "A" for An Unearthly Child
+    #[serde(default)]
+    pub story_code: Option<String>,
+    // Part of a multi-part classic serial
+    #[serde(default)]
+    pub part: Option<i32>,
+    #[serde(default)]
+    pub special: bool,
 }
 
-pub async fn episode_cmd_response(name: String) -> Result<EditInteractionResponse> {
-    // API key
-    let api_key: String = env::var("RAPID_API")?;
+impl Episode {
+    // Season and episode number as shown to users, e.g. "4x7", "classic 1x3" or "4x special"
+    pub fn number(&self) -> String {
+        let prefix = if self.era == "classic" {
+            "classic "
+        } else {
+            ""
+        };
+        if self.special {
+            format!("{}{}x special", prefix, self.season)
+        } else {
+            format!("{}{}x{}", prefix, self.season, self.episode)
+        }
+    }
+
+    // Title with the part of a serial, e.g. "The Daleks (part 3)"
+    pub fn full_title(&self) -> String {
+        match self.part {
+            Some(part) => format!("{} (part {})", self.title, part),
+            None => self.title.clone(),
+        }
+    }
+}
+
+fn default_era() -> String {
+    String::from("revival")
+}
+
+// Columns read by row_to_episode, in order
+pub const EPISODE_COLUMNS: &str =
+    "id, title, season, episode, era, doctor, air_date, story_code, part, special";
+
+// Most episodes a single search returns
+pub const MAX_RESULTS: usize = 10;
+
+pub fn row_to_episode(row: &rusqlite::Row) -> rusqlite::Result<Episode> {
+    Ok(Episode {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        season: row.get(2)?,
+        episode: row.get(3)?,
+        era: row.get(4)?,
+        doctor: row.get(5)?,
+        air_date: row.get(6)?,
+        story_code: row.get(7)?,
+        part: row.get(8)?,
+        special: row.get(9)?,
+    })
+}
+
+// Splits a search into lowercase words, dropping anything FTS5 would treat as syntax
+pub fn search_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+fn fts_search(conn: &Connection, query: &str, limit: usize) -> Result<Vec<Episode>> {
+    let mut stmt = conn.prepare(
+        "select e.id, e.title, e.season, e.episode, e.era, e.doctor, e.air_date,
+            e.story_code, e.part, e.special from episodes_fts
+        join episodes e on e.rowid = episodes_fts.rowid
+        where episodes_fts match ?1 order by bm25(episodes_fts), e.season, e.episode limit ?2",
+    )?;
+    let episodes = stmt
+        .query_map(params![query, limit as i64], row_to_episode)?
+        .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+    Ok(episodes)
+}
+
+// Typo tolerant fallback, ranks titles by how close their words are to the searched ones
+fn fuzzy_search(conn: &Connection, words: &[String], limit: usize) -> Result<Vec<Episode>> {
+    let mut stmt = conn.prepare(&format!("select {} from episodes", EPISODE_COLUMNS))?;
+    let episodes = stmt
+        .query_map([], row_to_episode)?
+        .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+    let mut scored: Vec<(usize, usize, Episode)> = Vec::new();
+    for episode in episodes {
+        let title_words = search_words(&episode.title);
+        let mut matched = 0;
+        let mut distance = 0;
+        for word in words {
+            let best = title_words
+                .iter()
+                .map(|t| edit_distance(word, t))
+                .min()
+                .unwrap_or(usize::MAX);
+            // Allow one typo per four letters
+            if best <= (word.chars().count() / 4).max(1) {
+                matched += 1;
+                distance += best;
+            }
+        }
+        if matched > 0 {
+            scored.push((matched, distance, episode));
+        }
+    }
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    Ok(scored.into_iter().take(limit).map(|(_, _, e)| e).collect())
+}
+
+// Ranked episode search: all words as prefixes, then any word, then a fuzzy match
+pub fn search_episodes(conn: &Connection, text: &str, limit: usize) -> Result<Vec<Episode>> {
+    let words = search_words(text);
+    if words.is_empty() {
+        return Ok(Vec::new());
+    }
+    let terms: Vec<String> = words.iter().map(|w| format!("\"{}\"*", w)).collect();
+    let episodes = fts_search(conn, &terms.join(" "), limit)?;
+    if !episodes.is_empty() {
+        return Ok(episodes);
+    }
+    if words.len() > 1 {
+        let episodes = fts_search(conn, &terms.join(" OR "), limit)?;
+        if !episodes.is_empty() {
+            return Ok(episodes);
+        }
+    }
+    fuzzy_search(conn, &words, limit)
+}
+
+// Discord shows at most 25 autocomplete choices
+const MAX_CHOICES: usize = 25;
+
+// Suggests episode titles for the name option while the user types
+pub fn episode_autocomplete(cmd: &CommandInteraction) -> Result<CreateAutocompleteResponse> {
+    let typed = match cmd.data.autocomplete() {
+        Some(option) => option.value.to_string(),
+        None => String::new(),
+    };
     let conn: Connection = Connection::open("episodes.db")?;
-    let pattern = name.replace(['\n', '\r', '\t'], "");
-    let search_cmd = String::from("select distinct * from episodes where title like '%")
-        + &pattern
-        + &String::from("%';");
-    let mut stmt: Statement<'_> = conn.prepare(&search_cmd)?;
-    let episodes_iter = stmt.query_map([], |row| {
-        Ok(Episode {
-            id: row.get(0).unwrap(),
-            title: row.get(1).unwrap(),
-            season: row.get(2).unwrap(),
-            episode: row.get(3).unwrap(),
+    let episodes = if search_words(&typed).is_empty() {
+        let mut stmt = conn.prepare(&format!(
+            "select {} from episodes order by era desc, season, episode limit ?1",
+            EPISODE_COLUMNS
+        ))?;
+        let episodes = stmt
+            .query_map(params![MAX_CHOICES as i64], row_to_episode)?
+            .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+        episodes
+    } else {
+        search_episodes(&conn, &typed, MAX_CHOICES)?
+    };
+    let mut response = CreateAutocompleteResponse::new();
+    for episode in episodes {
+        let label = format!("{} ({})", episode.full_title(), episode.number());
+        response = response.add_string_choice(label, episode.title);
+    }
+    Ok(response)
+}
+
+// Lead Doctor and companions by (season, first episode, last episode), in catalog numbering
+const SEASON_CAST: [(i32, i32, i32, u8, &str); 22] = [
+    (1, 0, 99, 9, "Rose Tyler"),
+    (2, 0, 99, 10, "Rose Tyler, Mickey Smith"),
+    (3, 0, 0, 10, "Donna Noble"),
+    (3, 1, 99, 10, "Martha Jones"),
+    (4, 0, 0, 10, "Astrid Peth"),
+    (4, 1, 99, 10, "Donna Noble"),
+    (5, 0, 99, 11, "Amy Pond, Rory Williams"),
+    (6, 0, 99, 11, "Amy Pond, Rory Williams, River Song"),
+    (7, 0, 5, 11, "Amy Pond, Rory Williams"),
+    (7, 6, 99, 11, "Clara Oswald"),
+    (8, 0, 0, 11, "Clara Oswald"),
+    (8, 1, 99, 12, "Clara Oswald"),
+    (9, 0, 99, 12, "Clara Oswald"),
+    (10, 0, 0, 12, "Nardole"),
+    (10, 1, 99, 12, "Bill Potts, Nardole"),
+    (11, 0, 0, 12, "Bill Potts"),
+    (11, 1, 99, 13, "Graham O'Brien, Ryan Sinclair, Yasmin Khan"),
+    (12, 0, 99, 13, "Graham O'Brien, Ryan Sinclair, Yasmin Khan"),
+    (13, 0, 99, 13, "Yasmin Khan, Dan Lewis"),
+    (14, 0, 99, 15, "Ruby Sunday"),
+    (15, 0, 0, 15, "Joy Almondo"),
+    (15, 1, 99, 15, "Belinda Chandra"),
+];
+
+// Episodes whose cast differs from the rest of their season, by catalog id. The 2023
+// specials share their season numbers with the Fifteenth Doctor's episodes, and the
+// Christmas specials numbered as episode 0 have their own travelling companions.
+const CAST_OVERRIDES: [(&str, u8, &str); 5] = [
+    ("tt1672218", 11, "Amy Pond, Rory Williams"),
+    ("tt1998643", 11, "Madge Arwell"),
+    ("tt29628321", 14, "Donna Noble"),
+    ("tt29628362", 14, "Donna Noble"),
+    ("tt29628376", 14, "Donna Noble"),
+];
+
+// Fills in the Doctor of catalog rows that don't have one, flags revival specials and
+// copies known air dates from the metadata cache
+pub fn backfill_catalog(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare(&format!(
+        "select {} from episodes where doctor is null",
+        EPISODE_COLUMNS
+    ))?;
+    let episodes = stmt
+        .query_map([], row_to_episode)?
+        .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+    for episode in episodes {
+        if let Some((doctor, _)) = episode_cast(&episode) {
+            conn.execute(
+                "update episodes set doctor = ?1 where id = ?2",
+                params![doctor, episode.id],
+            )?;
+        }
+    }
+    // Revival specials are numbered as episode 0 of the following season
+    conn.execute(
+        "update episodes set special = 1 where era = 'revival' and episode = 0 and special = 0",
+        [],
+    )?;
+    conn.execute(
+        "update episodes set air_date = (
+            select printf('%04d-%02d-%02d', release_year, release_month, release_day)
+            from title_metadata where title_metadata.id = episodes.id
+        )
+        where air_date is null and id in (
+            select id from title_metadata where release_year is not null
+            and release_month is not null and release_day is not null
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+// Doctor number and companions of an episode, if known
+pub fn episode_cast(episode: &Episode) -> Option<(u8, &'static str)> {
+    if episode.era != "revival" {
+        return episode.doctor.map(|doctor| (doctor, ""));
+    }
+    if let Some((_, doctor, companions)) = CAST_OVERRIDES.iter().find(|c| c.0 == episode.id) {
+        return Some((*doctor, *companions));
+    }
+    SEASON_CAST
+        .iter()
+        .find(|(season, first, last, _, _)| {
+            *season == episode.season && (*first..=*last).contains(&episode.episode)
         })
-    })?;
+        .map(|(_, _, _, doctor, companions)| (*doctor, *companions))
+}
+
+// Companions of the Doctors the revival cast tables don't cover, by Doctor ID. The classic
+// catalog only knows serials, not who travelled in them.
+const CLASSIC_COMPANIONS: [(&str, &str); 10] = [
+    ("fugitive", "Lee Clayton"),
+    ("first", "Susan Foreman, Ian Chesterton, Barbara Wright, Vicki, Steven Taylor, Katarina, Sara Kingdom, Dodo Chaplet, Polly, Ben Jackson"),
+    ("second", "Polly, Ben Jackson, Jamie McCrimmon, Victoria Waterfield, Zoe Heriot"),
+    ("third", "Liz Shaw, Jo Grant, Sarah Jane Smith"),
+    ("fourth", "Sarah Jane Smith, Harry Sullivan, Leela, K9, Romana, Adric, Nyssa, Tegan Jovanka"),
+    ("fifth", "Adric, Nyssa, Tegan Jovanka, Vislor Turlough, Kamelion, Peri Brown"),
+    ("sixth", "Peri Brown, Mel Bush"),
+    ("seventh", "Mel Bush, Ace"),
+    ("eighth", "Grace Holloway"),
+    ("war", "The Moment"),
+];
+
+// Companions travelling with a Doctor, in order of appearance
+pub fn doctor_companions(doctor: &Doctor) -> Vec<&'static str> {
+    let mut companions: Vec<&'static str> = Vec::new();
+    let classic = CLASSIC_COMPANIONS
+        .iter()
+        .filter(|(id, _)| *id == doctor.id)
+        .map(|(_, names)| *names);
+    let revival = SEASON_CAST
+        .iter()
+        .map(|(_, _, _, d, c)| (*d, *c))
+        .chain(CAST_OVERRIDES.iter().map(|(_, d, c)| (*d, *c)))
+        .filter(|(d, _)| Some(*d) == doctor.ordinal)
+        .map(|(_, names)| names);
+    for names in classic.chain(revival) {
+        for name in names.split(", ") {
+            if !companions.contains(&name) {
+                companions.push(name);
+            }
+        }
+    }
+    companions
+}
+
+pub fn get_episode(conn: &Connection, id: &str) -> Result<Option<Episode>> {
+    let episode = conn
+        .query_row(
+            &format!("select {} from episodes where id = ?1", EPISODE_COLUMNS),
+            params![id],
+            row_to_episode,
+        )
+        .optional()?;
+    Ok(episode)
+}
+
+// Detail card of a single episode
+pub fn episode_embed(episode: &Episode, metadata: Option<&TitleMetadata>) -> CreateEmbed {
+    let era = if episode.era == "classic" {
+        "Classic season"
+    } else {
+        "Season"
+    };
+    let mut embed = CreateEmbed::new().title(episode.full_title()).field(
+        "Episode",
+        if episode.special {
+            format!("{} {} special", era, episode.season)
+        } else {
+            format!("{} {}, episode {}", era, episode.season, episode.episode)
+        },
+        true,
+    );
+    if episode.id.starts_with("tt") {
+        embed = embed.url(format!("https://www.imdb.com/title/{}/", episode.id));
+    }
+    if let Some(code) = &episode.story_code {
+        embed = embed.field("Story code", code, true);
+    }
+    if let Some((doctor, companions)) = episode_cast(episode) {
+        embed = embed.field("Doctor", doctor_name(doctor), true);
+        if !companions.is_empty() {
+            embed = embed.field("Companions", companions, true);
+        }
+    }
+    match metadata {
+        Some(metadata) => {
+            embed = embed.field("Air date", metadata.release_date(), true);
+            if let Some(rating) = metadata.rating {
+                let votes = metadata.votes.unwrap_or(0);
+                embed = embed.field(
+                    "IMDb rating",
+                    format!("⭐ {:.1}/10 ({} votes)", rating, votes),
+                    true,
+                );
+            }
+            if let Some(runtime) = metadata.runtime {
+                embed = embed.field("Runtime", format!("{} min", runtime / 60), true);
+            }
+            if let Some(plot) = &metadata.plot {
+                embed = embed.description(plot);
+            }
+            if let Some(image) = &metadata.image {
+                embed = embed.thumbnail(image);
+            }
+        }
+        None => {
+            if let Some(date) = &episode.air_date {
+                embed = embed.field("Air date", date, true);
+            }
+            embed = embed.description("No details found for this episode");
+        }
+    }
+    embed
+}
+
+// Shows the episode picked from the select menu of a multi-match search
+pub async fn episode_select_response(
+    component: &ComponentInteraction,
+    http: &HttpClient,
+) -> Result<EditInteractionResponse> {
+    let id = match &component.data.kind {
+        ComponentInteractionDataKind::StringSelect { values } if !values.is_empty() => {
+            values[0].clone()
+        }
+        _ => return Err(anyhow::anyhow!("Failed to parse episode selection")),
+    };
+    let providers = metadata_providers(http);
+    let episode = match get_episode(&Connection::open("episodes.db")?, &id)? {
+        Some(body) => body,
+        None => {
+            return Ok(EditInteractionResponse::new().content("That episode is no longer available"))
+        }
+    };
+    let metadata = title_metadata(&providers, &episode).await?;
+    Ok(EditInteractionResponse::new().embed(episode_embed(&episode, metadata.as_ref())))
+}
+
+pub async fn episode_cmd_response(
+    name: String,
+    http: &HttpClient,
+) -> Result<EditInteractionResponse> {
+    // Without a reachable provider only cached metadata is shown
+    let providers = metadata_providers(http);
+    let mut episodes = search_episodes(&Connection::open("episodes.db")?, &name, MAX_RESULTS)?;
+    // A title picked from autocomplete only shows that episode, or the parts of that serial
+    if episodes
+        .iter()
+        .any(|e| e.title.eq_ignore_ascii_case(name.trim()))
+    {
+        episodes.retain(|e| e.title.eq_ignore_ascii_case(name.trim()));
+    }
+    if episodes.is_empty() {
+        return Ok(EditInteractionResponse::new().content("No episodes match your search"));
+    }
+    if episodes.len() == 1 {
+        let episode = &episodes[0];
+        let metadata = title_metadata(&providers, episode).await?;
+        return Ok(EditInteractionResponse::new().embed(episode_embed(episode, metadata.as_ref())));
+    }
+    let options = episodes
+        .iter()
+        .map(|e| {
+            CreateSelectMenuOption::new(
+                format!("{} ({})", e.full_title(), e.number()),
+                e.id.clone(),
+            )
+        })
+        .collect();
+    let menu = CreateSelectMenu::new("episode_select", CreateSelectMenuKind::String { options })
+        .placeholder("Pick an episode to see its details");
+    // Look the results up in parallel, the HTTP client bounds how many run at once
+    let lookups = join_all(
+        episodes
+            .iter()
+            .map(|episode| title_metadata(&providers, episode)),
+    )
+    .await;
     let mut msg_content: String =
         String::from("## Here are the episodes that match your search:\n");
-    for episode in episodes_iter {
-        match episode {
-            Ok(episode) => {
-                let url = format!(
-                    "https://moviesdatabase.p.rapidapi.com/titles/{}",
-                    episode.id
-                );
-                let http_req = ureq::get(&url)
-                    .set("X-RapidAPI-Key", &api_key)
-                    .set("X-RapidAPI-Host", "moviesdatabase.p.rapidapi.com");
-                let api_response = match http_req.call() {
-                    Ok(body) => Some(body.into_string().unwrap_or(String::from(""))),
-                    Err(_) => None,
-                };
-                let title = format!("__{}__: ", episode.title);
-                msg_content.push_str(&title);
-                match api_response {
-                    Some(body) => {
-                        let resp_json: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
-                        let year: i64 = resp_json["results"]["releaseDate"]["year"]
-                            .as_i64()
-                            .unwrap_or(0);
-                        let month: i64 = resp_json["results"]["releaseDate"]["month"]
-                            .as_i64()
-                            .unwrap_or(0);
-                        let day: i64 = resp_json["results"]["releaseDate"]["day"]
-                            .as_i64()
-                            .unwrap_or(0);
-                        let details = format!(
-                            " {}-{}-{}    **({}x{})**\n",
-                            year, month, day, episode.season, episode.episode
-                        );
-                        msg_content.push_str(&details);
-                    }
-                    None => {
-                        msg_content.push_str(" [no data found]");
-                        msg_content.push('\n');
-                    }
-                }
+    for (episode, metadata) in episodes.iter().zip(lookups) {
+        let title = format!("__{}__: ", episode.full_title());
+        msg_content.push_str(&title);
+        let release_date = metadata?
+            .map(|m| m.release_date())
+            .or(episode.air_date.clone());
+        match release_date {
+            Some(date) => {
+                let details = format!(" {}    **({})**\n", date, episode.number());
+                msg_content.push_str(&details);
             }
-            Err(e) => {
-                println!("Error: {}", e);
+            None => {
+                msg_content.push_str(" [no data found]");
+                msg_content.push('\n');
             }
         }
     }
-    Ok(EditInteractionResponse::new().content(msg_content))
+    Ok(EditInteractionResponse::new()
+        .content(msg_content)
+        .components(vec![CreateActionRow::SelectMenu(menu)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_words_split_on_punctuation_and_lowercase() {
+        assert_eq!(
+            search_words("The Doctor, the Widow and the Wardrobe"),
+            vec!["the", "doctor", "the", "widow", "and", "the", "wardrobe"]
+        );
+        assert_eq!(
+            search_words("Dalek's \"Asylum\"!"),
+            vec!["dalek", "s", "asylum"]
+        );
+    }
+
+    #[test]
+    fn search_words_ignore_empty_text() {
+        assert!(search_words("").is_empty());
+        assert!(search_words(" -- ?! ").is_empty());
+    }
+
+    #[test]
+    fn search_words_keep_numbers_and_accents() {
+        assert_eq!(search_words("Episode #14.5"), vec!["episode", "14", "5"]);
+        assert_eq!(search_words("Rosé"), vec!["rosé"]);
+    }
 }
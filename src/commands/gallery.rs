@@ -0,0 +1,50 @@
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Curated Doctor pictures live here, described by gallery.json
+pub const GALLERY_DIR: &str = "gallery";
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GalleryImage {
+    // File name inside the gallery directory
+    pub file: String,
+    // Photographer, licence or source, shown under the picture
+    pub credit: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GalleryEntry {
+    // ID of the Doctor, e.g. "tenth" or "war"
+    pub doctor: String,
+    pub actor: String,
+    pub era: String,
+    #[serde(default)]
+    pub images: Vec<GalleryImage>,
+}
+
+impl GalleryEntry {
+    // A random curated picture whose file is actually present
+    pub fn pick_image(&self) -> Option<(&GalleryImage, PathBuf)> {
+        let present: Vec<(&GalleryImage, PathBuf)> = self
+            .images
+            .iter()
+            .map(|image| (image, Path::new(GALLERY_DIR).join(&image.file)))
+            .filter(|(_, path)| path.is_file())
+            .collect();
+        present.choose(&mut rand::thread_rng()).cloned()
+    }
+}
+
+pub fn load_gallery() -> Result<Vec<GalleryEntry>> {
+    let path = Path::new(GALLERY_DIR).join("gallery.json");
+    let gallery: Vec<GalleryEntry> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    Ok(gallery)
+}
+
+pub fn gallery_entry(doctor: &str) -> Result<Option<GalleryEntry>> {
+    let gallery = load_gallery()?;
+    Ok(gallery.into_iter().find(|entry| entry.doctor == doctor))
+}
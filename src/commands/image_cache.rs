@@ -0,0 +1,253 @@
+use crate::commands::image_policy::{
+    blacklist_image, check_image, check_url, is_blacklisted, max_image_bytes,
+};
+use crate::http::HttpClient;
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Downloaded images are kept here, named by the hash of their content
+pub const IMAGE_CACHE_DIR: &str = "image_cache";
+// Size of the cache before the least recently used images are evicted, overridable
+// with IMAGE_CACHE_MB
+const DEFAULT_CACHE_MB: u64 = 50;
+
+#[derive(Debug, Clone)]
+pub struct CachedImage {
+    pub key: String,
+    pub url: String,
+    pub hash: String,
+    pub file: String,
+    pub content_type: String,
+    pub size: i64,
+    pub fetched_at: i64,
+    pub last_used: i64,
+}
+
+impl CachedImage {
+    pub fn path(&self) -> PathBuf {
+        Path::new(IMAGE_CACHE_DIR).join(&self.file)
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+fn cache_limit() -> i64 {
+    let mb = env::var("IMAGE_CACHE_MB")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_CACHE_MB);
+    (mb * 1024 * 1024) as i64
+}
+
+fn extension(content_type: &str) -> &'static str {
+    match content_type {
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        _ => "jpg",
+    }
+}
+
+// Cached image of a key, marked as used. Rows whose file went missing or that moderators
+// blocked are dropped.
+pub fn cached_image(conn: &Connection, key: &str) -> Result<Option<CachedImage>> {
+    let image = conn
+        .query_row(
+            "select key, url, hash, file, content_type, size, fetched_at, last_used
+            from image_cache where key = ?1",
+            params![key],
+            |row| {
+                Ok(CachedImage {
+                    key: row.get(0)?,
+                    url: row.get(1)?,
+                    hash: row.get(2)?,
+                    file: row.get(3)?,
+                    content_type: row.get(4)?,
+                    size: row.get(5)?,
+                    fetched_at: row.get(6)?,
+                    last_used: row.get(7)?,
+                })
+            },
+        )
+        .optional()?;
+    let mut image = match image {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    if !image.path().is_file() || is_blacklisted(conn, Some(&image.hash), &image.url)? {
+        conn.execute("delete from image_cache where key = ?1", params![key])?;
+        remove_unused_file(conn, &image.file)?;
+        return Ok(None);
+    }
+    image.last_used = now();
+    conn.execute(
+        "update image_cache set last_used = ?1 where key = ?2",
+        params![image.last_used, key],
+    )?;
+    Ok(Some(image))
+}
+
+// Writes an image under its content hash and records it for the key. Identical images
+// fetched for different keys share one file, images larger than the whole cache are refused.
+pub fn store_image(
+    conn: &Connection,
+    key: &str,
+    url: &str,
+    bytes: &[u8],
+    content_type: &str,
+) -> Result<CachedImage> {
+    let limit = cache_limit();
+    if bytes.len() as i64 > limit {
+        return Err(anyhow::anyhow!(
+            "image is {} bytes, the cache holds {}",
+            bytes.len(),
+            limit
+        ));
+    }
+    let hash = format!("{:x}", Sha256::digest(bytes));
+    let file = format!("{}.{}", hash, extension(content_type));
+    fs::create_dir_all(IMAGE_CACHE_DIR)?;
+    let path = Path::new(IMAGE_CACHE_DIR).join(&file);
+    if !path.is_file() {
+        fs::write(&path, bytes)?;
+    }
+    let image = CachedImage {
+        key: key.to_string(),
+        url: url.to_string(),
+        hash,
+        file,
+        content_type: content_type.to_string(),
+        size: bytes.len() as i64,
+        fetched_at: now(),
+        last_used: now(),
+    };
+    let replaced: Option<String> = conn
+        .query_row(
+            "select file from image_cache where key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()?;
+    conn.execute(
+        "insert into image_cache (key, url, hash, file, content_type, size, fetched_at, last_used)
+        values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        on conflict(key) do update set url = excluded.url, hash = excluded.hash,
+            file = excluded.file, content_type = excluded.content_type, size = excluded.size,
+            fetched_at = excluded.fetched_at, last_used = excluded.last_used",
+        params![
+            image.key,
+            image.url,
+            image.hash,
+            image.file,
+            image.content_type,
+            image.size,
+            image.fetched_at,
+            image.last_used
+        ],
+    )?;
+    if let Some(old) = replaced {
+        remove_unused_file(conn, &old)?;
+    }
+    evict(conn, limit, &image.file)?;
+    Ok(image)
+}
+
+// Deletes a cached file once no key refers to it any more
+fn remove_unused_file(conn: &Connection, file: &str) -> Result<()> {
+    let users: i64 = conn.query_row(
+        "select count(*) from image_cache where file = ?1",
+        params![file],
+        |row| row.get(0),
+    )?;
+    if users == 0 {
+        let path = Path::new(IMAGE_CACHE_DIR).join(file);
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+// Drops the least recently used images until the files fit in the limit, the file that
+// was just stored is kept
+fn evict(conn: &Connection, limit: i64, keep: &str) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "select file, max(size), max(last_used) as used from image_cache
+        group by file order by used",
+    )?;
+    let files = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, i64)>, rusqlite::Error>>()?;
+    let mut total: i64 = files.iter().map(|(_, size)| size).sum();
+    for (file, size) in files {
+        if total <= limit {
+            break;
+        }
+        if file == keep {
+            continue;
+        }
+        conn.execute("delete from image_cache where file = ?1", params![file])?;
+        remove_unused_file(conn, &file)?;
+        total -= size;
+    }
+    Ok(())
+}
+
+// Cached image of a key, downloading it from the URL on a miss. Downloads have to pass
+// the image policy before they are cached.
+pub async fn fetch_cached(http: &HttpClient, key: &str, url: &str) -> Result<Option<CachedImage>> {
+    if let Some(image) = cached_image(&Connection::open("episodes.db")?, key)? {
+        if image.url == url {
+            return Ok(Some(image));
+        }
+    }
+    check_url(url)?;
+    if is_blacklisted(&Connection::open("episodes.db")?, None, url)? {
+        return Err(anyhow::anyhow!("{} was blocked by a moderator", url));
+    }
+    let (bytes, content_type) = match http.bytes(http.download(url), max_image_bytes()).await? {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    let mime = check_image(&bytes, content_type.as_deref())?;
+    let conn = Connection::open("episodes.db")?;
+    let hash = format!("{:x}", Sha256::digest(&bytes));
+    if is_blacklisted(&conn, Some(&hash), url)? {
+        return Err(anyhow::anyhow!("{} was blocked by a moderator", url));
+    }
+    let image = store_image(&conn, key, url, &bytes, mime)?;
+    Ok(Some(image))
+}
+
+// Blocks the cached picture of a key so it is never served or cached again
+pub fn block_cached_image(
+    conn: &Connection,
+    key: &str,
+    reason: &str,
+    blocked_by: &str,
+) -> Result<Option<CachedImage>> {
+    let image = match cached_image(conn, key)? {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    blacklist_image(conn, &image.hash, &image.url, reason, blocked_by)?;
+    let mut stmt = conn.prepare("select key, file from image_cache where hash = ?1")?;
+    let blocked = stmt
+        .query_map(params![image.hash], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()?;
+    for (key, file) in blocked {
+        conn.execute("delete from image_cache where key = ?1", params![key])?;
+        remove_unused_file(conn, &file)?;
+    }
+    Ok(Some(image))
+}
@@ -0,0 +1,171 @@
+use anyhow::Result;
+use reqwest::Url;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::env;
+
+// Largest picture we attach, overridable with IMAGE_MAX_BYTES
+const DEFAULT_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+// Safesearch level sent to the image search, on unless IMAGE_SAFESEARCH says otherwise
+pub fn safesearch() -> String {
+    match env::var("IMAGE_SAFESEARCH") {
+        Ok(level) if ["on", "moderate", "off"].contains(&level.as_str()) => level,
+        _ => String::from("on"),
+    }
+}
+
+fn domain_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|domain| domain.trim().trim_start_matches('.').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+// A host matches a listed domain and any of its subdomains
+fn host_matches(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+// Rejects URLs that aren't http(s) or whose host is denied, or not allowed when
+// IMAGE_ALLOWED_DOMAINS is set. IMAGE_DENIED_DOMAINS always wins.
+pub fn check_url(url: &str) -> Result<()> {
+    check_url_with(
+        url,
+        &domain_list("IMAGE_DENIED_DOMAINS"),
+        &domain_list("IMAGE_ALLOWED_DOMAINS"),
+    )
+}
+
+fn check_url_with(url: &str, denied: &[String], allowed: &[String]) -> Result<()> {
+    let parsed = Url::parse(url)?;
+    if !["http", "https"].contains(&parsed.scheme()) {
+        return Err(anyhow::anyhow!("{} isn't an http URL", url));
+    }
+    let host = match parsed.host_str() {
+        Some(body) => body.to_lowercase(),
+        None => return Err(anyhow::anyhow!("{} has no host", url)),
+    };
+    if denied.iter().any(|domain| host_matches(&host, domain)) {
+        return Err(anyhow::anyhow!("{} is on the denied domains", host));
+    }
+    if !allowed.is_empty() && !allowed.iter().any(|domain| host_matches(&host, domain)) {
+        return Err(anyhow::anyhow!("{} isn't on the allowed domains", host));
+    }
+    Ok(())
+}
+
+// Image type from the first bytes of the file, servers don't always send the right one
+fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() > 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+pub fn max_image_bytes() -> usize {
+    env::var("IMAGE_MAX_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BYTES)
+}
+
+// Checks a downloaded picture before it is cached or attached, returns its MIME type
+pub fn check_image(bytes: &[u8], content_type: Option<&str>) -> Result<&'static str> {
+    let max_bytes = max_image_bytes();
+    if bytes.is_empty() || bytes.len() > max_bytes {
+        return Err(anyhow::anyhow!(
+            "image is {} bytes, the limit is {}",
+            bytes.len(),
+            max_bytes
+        ));
+    }
+    if let Some(content_type) = content_type {
+        if !content_type.starts_with("image/") {
+            return Err(anyhow::anyhow!("{} isn't an image", content_type));
+        }
+    }
+    match sniff_mime(bytes) {
+        Some(mime) => Ok(mime),
+        None => Err(anyhow::anyhow!("unsupported image format")),
+    }
+}
+
+// Whether moderators blocked this picture, by content hash or source URL
+pub fn is_blacklisted(conn: &Connection, hash: Option<&str>, url: &str) -> Result<bool> {
+    let blocked: Option<String> = conn
+        .query_row(
+            "select hash from image_blacklist where hash = ?1 or url = ?2",
+            params![hash, url],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(blocked.is_some())
+}
+
+pub fn blacklist_image(
+    conn: &Connection,
+    hash: &str,
+    url: &str,
+    reason: &str,
+    blocked_by: &str,
+) -> Result<()> {
+    conn.execute(
+        "insert into image_blacklist (hash, url, reason, blocked_by, blocked_at)
+        values (?1, ?2, ?3, ?4, datetime('now'))
+        on conflict(hash) do update set reason = excluded.reason,
+            blocked_by = excluded.blocked_by, blocked_at = excluded.blocked_at",
+        params![hash, url, reason, blocked_by],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domains(list: &[&str]) -> Vec<String> {
+        list.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn only_http_urls_with_a_host_pass() {
+        assert!(check_url_with("https://example.org/tardis.png", &[], &[]).is_ok());
+        assert!(check_url_with("http://example.org/tardis.png", &[], &[]).is_ok());
+        assert!(check_url_with("ftp://example.org/tardis.png", &[], &[]).is_err());
+        assert!(check_url_with("file:///etc/passwd", &[], &[]).is_err());
+        assert!(check_url_with("not a url", &[], &[]).is_err());
+    }
+
+    #[test]
+    fn denied_domains_cover_their_subdomains() {
+        let denied = domains(&["bad.example"]);
+        assert!(check_url_with("https://bad.example/a.png", &denied, &[]).is_err());
+        assert!(check_url_with("https://img.BAD.example/a.png", &denied, &[]).is_err());
+        assert!(check_url_with("https://notbad.example/a.png", &denied, &[]).is_ok());
+    }
+
+    #[test]
+    fn allowed_domains_restrict_every_other_host() {
+        let allowed = domains(&["wikimedia.org"]);
+        assert!(check_url_with("https://upload.wikimedia.org/a.jpg", &[], &allowed).is_ok());
+        assert!(check_url_with("https://example.org/a.jpg", &[], &allowed).is_err());
+        assert!(check_url_with("https://wikimedia.org.evil.example/a.jpg", &[], &allowed).is_err());
+    }
+
+    #[test]
+    fn denied_domains_win_over_allowed_ones() {
+        let denied = domains(&["upload.wikimedia.org"]);
+        let allowed = domains(&["wikimedia.org"]);
+        assert!(check_url_with("https://upload.wikimedia.org/a.jpg", &denied, &allowed).is_err());
+        assert!(check_url_with("https://commons.wikimedia.org/a.jpg", &denied, &allowed).is_ok());
+    }
+}
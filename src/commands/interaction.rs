@@ -0,0 +1,40 @@
+use anyhow::Result;
+use serenity::all::{CommandInteraction, Member, Permissions, ResolvedOption, ResolvedValue};
+use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseMessage};
+
+pub fn message(content: impl Into<String>) -> CreateInteractionResponse {
+    CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content(content))
+}
+
+// A reply only the member who used the command sees
+pub fn ephemeral(content: impl Into<String>) -> CreateInteractionResponse {
+    CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    )
+}
+
+// Name and options of the subcommand a command was run with
+pub fn subcommand<'a>(
+    cmd: &'a CommandInteraction,
+    command: &str,
+) -> Result<(&'a str, Vec<ResolvedOption<'a>>)> {
+    let mut options = cmd.data.options();
+    if options.is_empty() {
+        return Err(anyhow::anyhow!("Failed to parse {} command", command));
+    }
+    let option = options.remove(0);
+    match option.value {
+        ResolvedValue::SubCommand(sub_options) => Ok((option.name, sub_options)),
+        _ => Err(anyhow::anyhow!("Failed to parse {} command", command)),
+    }
+}
+
+// Whether the member has the permission in the channel, outside of guilds nobody has
+pub fn has_permission(member: Option<&Member>, permission: Permissions) -> bool {
+    member
+        .and_then(|m| m.permissions)
+        .map(|p| p.contains(permission))
+        .unwrap_or(false)
+}
@@ -0,0 +1,478 @@
+use crate::commands::episode::{row_to_episode, Episode, EPISODE_COLUMNS};
+use crate::http::HttpClient;
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use serenity::async_trait;
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Cached entries are refreshed after this many days, overridable with METADATA_TTL_DAYS
+const DEFAULT_TTL_DAYS: u64 = 30;
+
+// TVmaze show of the 2005 revival, overridable with TVMAZE_SHOW_ID
+const DEFAULT_TVMAZE_SHOW: &str = "210";
+
+#[derive(Debug, Clone, Default)]
+pub struct TitleMetadata {
+    pub id: String,
+    pub release_year: Option<i64>,
+    pub release_month: Option<i64>,
+    pub release_day: Option<i64>,
+    pub rating: Option<f64>,
+    pub votes: Option<i64>,
+    pub plot: Option<String>,
+    pub runtime: Option<i64>,
+    pub image: Option<String>,
+    pub fetched_at: i64,
+}
+
+impl TitleMetadata {
+    // Air date as year-month-day, missing parts are shown as 0
+    pub fn release_date(&self) -> String {
+        format!(
+            "{}-{}-{}",
+            self.release_year.unwrap_or(0),
+            self.release_month.unwrap_or(0),
+            self.release_day.unwrap_or(0)
+        )
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+pub fn ttl() -> Duration {
+    let days = env::var("METADATA_TTL_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TTL_DAYS);
+    Duration::from_secs(days * 24 * 60 * 60)
+}
+
+pub fn get_cached(conn: &Connection, id: &str) -> Result<Option<TitleMetadata>> {
+    let cached = conn
+        .query_row(
+            "select id, release_year, release_month, release_day, rating, votes, plot, runtime, image, fetched_at
+            from title_metadata where id = ?1",
+            params![id],
+            |row| {
+                Ok(TitleMetadata {
+                    id: row.get(0)?,
+                    release_year: row.get(1)?,
+                    release_month: row.get(2)?,
+                    release_day: row.get(3)?,
+                    rating: row.get(4)?,
+                    votes: row.get(5)?,
+                    plot: row.get(6)?,
+                    runtime: row.get(7)?,
+                    image: row.get(8)?,
+                    fetched_at: row.get(9)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(cached)
+}
+
+pub fn is_fresh(metadata: &TitleMetadata) -> bool {
+    now() - metadata.fetched_at < ttl().as_secs() as i64
+}
+
+pub fn store(conn: &Connection, metadata: &TitleMetadata) -> Result<()> {
+    conn.execute(
+        "insert into title_metadata (id, release_year, release_month, release_day, rating, votes, plot, runtime, image, fetched_at)
+        values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+        on conflict(id) do update set release_year = excluded.release_year,
+            release_month = excluded.release_month, release_day = excluded.release_day,
+            rating = excluded.rating, votes = excluded.votes, plot = excluded.plot,
+            runtime = excluded.runtime, image = excluded.image, fetched_at = excluded.fetched_at",
+        params![
+            metadata.id,
+            metadata.release_year,
+            metadata.release_month,
+            metadata.release_day,
+            metadata.rating,
+            metadata.votes,
+            metadata.plot,
+            metadata.runtime,
+            metadata.image,
+            metadata.fetched_at
+        ],
+    )?;
+    conn.execute(
+        "delete from title_metadata_missing where id = ?1",
+        params![metadata.id],
+    )?;
+    // Keep the catalog's air date in step for date based browsing
+    if let (Some(year), Some(month), Some(day)) = (
+        metadata.release_year,
+        metadata.release_month,
+        metadata.release_day,
+    ) {
+        conn.execute(
+            "update episodes set air_date = ?1 where id = ?2",
+            params![format!("{:04}-{:02}-{:02}", year, month, day), metadata.id],
+        )?;
+    }
+    Ok(())
+}
+
+// Whether every online provider recently answered that it doesn't know the episode
+pub fn is_missing(conn: &Connection, id: &str) -> Result<bool> {
+    let checked_at: Option<i64> = conn
+        .query_row(
+            "select checked_at from title_metadata_missing where id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(checked_at.is_some_and(|t| now() - t < ttl().as_secs() as i64))
+}
+
+pub fn store_missing(conn: &Connection, id: &str) -> Result<()> {
+    conn.execute(
+        "insert into title_metadata_missing (id, checked_at) values (?1, ?2)
+        on conflict(id) do update set checked_at = excluded.checked_at",
+        params![id, now()],
+    )?;
+    Ok(())
+}
+
+// A source of episode details. Fetched metadata is cached in title_metadata under the
+// catalog ID whichever provider it came from.
+#[async_trait]
+pub trait EpisodeMetadataProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Whether the provider only reads what is already on disk, prefetching skips those
+    fn offline(&self) -> bool {
+        false
+    }
+
+    // Details of a catalog episode, None when the provider doesn't know it
+    async fn fetch(&self, episode: &Episode) -> Result<Option<TitleMetadata>>;
+}
+
+// Cached and catalog data only, never touches the network. Serves stale cache entries
+// and otherwise the catalog air date, which is never cached itself.
+pub struct LocalProvider;
+
+#[async_trait]
+impl EpisodeMetadataProvider for LocalProvider {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn offline(&self) -> bool {
+        true
+    }
+
+    async fn fetch(&self, episode: &Episode) -> Result<Option<TitleMetadata>> {
+        if let Some(metadata) = get_cached(&Connection::open("episodes.db")?, &episode.id)? {
+            return Ok(Some(metadata));
+        }
+        let date: Vec<i64> = match &episode.air_date {
+            Some(date) => date
+                .split('-')
+                .filter_map(|part| part.parse().ok())
+                .collect(),
+            None => return Ok(None),
+        };
+        Ok(Some(TitleMetadata {
+            id: episode.id.clone(),
+            release_year: date.first().copied(),
+            release_month: date.get(1).copied(),
+            release_day: date.get(2).copied(),
+            ..TitleMetadata::default()
+        }))
+    }
+}
+
+// RapidAPI moviesdatabase, looks episodes up by their IMDb ID
+pub struct MoviesDatabase {
+    http: HttpClient,
+    api_key: String,
+}
+
+// Reads the fields we keep from a moviesdatabase title response
+pub fn parse_title(id: &str, resp_json: &Value) -> TitleMetadata {
+    let results = &resp_json["results"];
+    TitleMetadata {
+        id: id.to_string(),
+        release_year: results["releaseDate"]["year"].as_i64(),
+        release_month: results["releaseDate"]["month"].as_i64(),
+        release_day: results["releaseDate"]["day"].as_i64(),
+        rating: results["ratingsSummary"]["aggregateRating"].as_f64(),
+        votes: results["ratingsSummary"]["voteCount"].as_i64(),
+        plot: results["plot"]["plotText"]["plainText"]
+            .as_str()
+            .map(String::from),
+        runtime: results["runtime"]["seconds"].as_i64(),
+        image: results["primaryImage"]["url"].as_str().map(String::from),
+        fetched_at: now(),
+    }
+}
+
+#[async_trait]
+impl EpisodeMetadataProvider for MoviesDatabase {
+    fn name(&self) -> &'static str {
+        "moviesdatabase"
+    }
+
+    async fn fetch(&self, episode: &Episode) -> Result<Option<TitleMetadata>> {
+        // Only IMDb titles can be looked up, curated classic parts use their own IDs
+        if !episode.id.starts_with("tt") {
+            return Ok(None);
+        }
+        let url = format!(
+            "https://moviesdatabase.p.rapidapi.com/titles/{}?info=base_info",
+            episode.id
+        );
+        let request = self
+            .http
+            .get(&url)
+            .header("X-RapidAPI-Key", &self.api_key)
+            .header("X-RapidAPI-Host", "moviesdatabase.p.rapidapi.com");
+        let resp_json = match self.http.quota_json(request).await? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        if resp_json["results"].is_null() {
+            return Ok(None);
+        }
+        Ok(Some(parse_title(&episode.id, &resp_json)))
+    }
+}
+
+// TVmaze, free and keyless, looks revival episodes up by season and number
+pub struct TvMaze {
+    http: HttpClient,
+    show: String,
+}
+
+// TVmaze summaries are HTML, embeds want plain text
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+// Reads the fields we keep from a TVmaze episode response
+pub fn parse_tvmaze_episode(id: &str, resp_json: &Value) -> TitleMetadata {
+    let date: Vec<i64> = resp_json["airdate"]
+        .as_str()
+        .unwrap_or_default()
+        .split('-')
+        .filter_map(|part| part.parse().ok())
+        .collect();
+    TitleMetadata {
+        id: id.to_string(),
+        release_year: date.first().copied(),
+        release_month: date.get(1).copied(),
+        release_day: date.get(2).copied(),
+        rating: resp_json["rating"]["average"].as_f64(),
+        votes: None,
+        plot: resp_json["summary"].as_str().map(strip_tags),
+        runtime: resp_json["runtime"].as_i64().map(|minutes| minutes * 60),
+        image: resp_json["image"]["original"].as_str().map(String::from),
+        fetched_at: now(),
+    }
+}
+
+#[async_trait]
+impl EpisodeMetadataProvider for TvMaze {
+    fn name(&self) -> &'static str {
+        "tvmaze"
+    }
+
+    async fn fetch(&self, episode: &Episode) -> Result<Option<TitleMetadata>> {
+        // Specials have no episode number on TVmaze and classic serials are a separate show
+        if episode.era != "revival" || episode.special || episode.episode == 0 {
+            return Ok(None);
+        }
+        let url = format!(
+            "https://api.tvmaze.com/shows/{}/episodebynumber?season={}&number={}",
+            self.show, episode.season, episode.episode
+        );
+        let resp_json = match self.http.json(self.http.get(&url)).await? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        Ok(Some(parse_tvmaze_episode(&episode.id, &resp_json)))
+    }
+}
+
+// Providers to ask in order, picked with METADATA_PROVIDER (moviesdatabase, tvmaze or local).
+// moviesdatabase falls back to TVmaze when the API key is missing or the quota is used up.
+// The local provider ends every chain, and is the only one with local.
+pub fn metadata_providers(http: &HttpClient) -> Vec<Box<dyn EpisodeMetadataProvider>> {
+    let tvmaze = TvMaze {
+        http: http.clone(),
+        show: env::var("TVMAZE_SHOW_ID").unwrap_or(String::from(DEFAULT_TVMAZE_SHOW)),
+    };
+    let provider = env::var("METADATA_PROVIDER").unwrap_or_default();
+    match provider.to_lowercase().as_str() {
+        "local" => vec![Box::new(LocalProvider)],
+        "tvmaze" => vec![Box::new(tvmaze), Box::new(LocalProvider)],
+        _ => {
+            let mut providers: Vec<Box<dyn EpisodeMetadataProvider>> = Vec::new();
+            if let Ok(api_key) = env::var("RAPID_API") {
+                providers.push(Box::new(MoviesDatabase {
+                    http: http.clone(),
+                    api_key,
+                }));
+            }
+            providers.push(Box::new(tvmaze));
+            providers.push(Box::new(LocalProvider));
+            providers
+        }
+    }
+}
+
+// What asking the providers about an episode came to
+enum Lookup {
+    // An online provider knows the episode, worth caching
+    Fetched(TitleMetadata),
+    // Every online provider answered it doesn't know the episode, with the local data if any
+    Missing(Option<TitleMetadata>),
+    // No online provider could answer, with the local data if any
+    Unavailable(Option<TitleMetadata>),
+}
+
+// Asks each provider in turn until an online one knows the episode
+async fn fetch_metadata(providers: &[&dyn EpisodeMetadataProvider], episode: &Episode) -> Lookup {
+    let mut local = None;
+    let mut asked = false;
+    let mut failed = false;
+    for provider in providers {
+        match provider.fetch(episode).await {
+            Ok(Some(metadata)) if provider.offline() => {
+                local = local.or(Some(metadata));
+            }
+            Ok(Some(metadata)) => return Lookup::Fetched(metadata),
+            Ok(None) => asked |= !provider.offline(),
+            Err(e) => {
+                failed = true;
+                println!(
+                    "Failed to fetch metadata for {} from {}: {}",
+                    episode.id,
+                    provider.name(),
+                    e
+                );
+            }
+        }
+    }
+    if asked && !failed {
+        Lookup::Missing(local)
+    } else {
+        Lookup::Unavailable(local)
+    }
+}
+
+// Cached metadata while it is fresh, otherwise fetched and cached. A stale entry is
+// still better than nothing when no provider can be reached. Episodes the online providers
+// don't know are only looked up locally until the TTL runs out. The connection isn't held
+// across the fetch so the future stays Send.
+pub async fn title_metadata(
+    providers: &[Box<dyn EpisodeMetadataProvider>],
+    episode: &Episode,
+) -> Result<Option<TitleMetadata>> {
+    let (cached, missing) = {
+        let conn = Connection::open("episodes.db")?;
+        (
+            get_cached(&conn, &episode.id)?,
+            is_missing(&conn, &episode.id)?,
+        )
+    };
+    if let Some(metadata) = &cached {
+        if is_fresh(metadata) {
+            return Ok(cached);
+        }
+    }
+    let providers: Vec<&dyn EpisodeMetadataProvider> = providers
+        .iter()
+        .map(|p| p.as_ref())
+        .filter(|p| !missing || p.offline())
+        .collect();
+    match fetch_metadata(&providers, episode).await {
+        Lookup::Fetched(metadata) => {
+            store(&Connection::open("episodes.db")?, &metadata)?;
+            Ok(Some(metadata))
+        }
+        Lookup::Missing(local) => {
+            store_missing(&Connection::open("episodes.db")?, &episode.id)?;
+            Ok(cached.or(local))
+        }
+        Lookup::Unavailable(local) => Ok(cached.or(local)),
+    }
+}
+
+// Fills the cache for every episode in the catalog, used by the prefetch subcommand
+pub async fn prefetch_metadata(conn: &Connection, http: &HttpClient, force: bool) -> Result<()> {
+    let all_providers = metadata_providers(http);
+    let providers: Vec<&dyn EpisodeMetadataProvider> = all_providers
+        .iter()
+        .map(|p| p.as_ref())
+        .filter(|p| !p.offline())
+        .collect();
+    if providers.is_empty() {
+        return Err(anyhow::anyhow!(
+            "METADATA_PROVIDER is local, there is nothing to fetch from"
+        ));
+    }
+    let mut stmt = conn.prepare(&format!(
+        "select {} from episodes order by era desc, season, episode, part",
+        EPISODE_COLUMNS
+    ))?;
+    let episodes = stmt
+        .query_map([], row_to_episode)?
+        .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+    let mut fetched = 0;
+    let mut missing = 0;
+    for episode in &episodes {
+        if !force {
+            if let Some(metadata) = get_cached(conn, &episode.id)? {
+                if is_fresh(&metadata) {
+                    continue;
+                }
+            }
+            if is_missing(conn, &episode.id)? {
+                missing += 1;
+                continue;
+            }
+        }
+        match fetch_metadata(&providers, episode).await {
+            Lookup::Fetched(metadata) => {
+                store(conn, &metadata)?;
+                fetched += 1;
+            }
+            Lookup::Missing(_) => {
+                store_missing(conn, &episode.id)?;
+                missing += 1;
+            }
+            Lookup::Unavailable(_) => missing += 1,
+        }
+        // Stay well under the API rate limits
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+    println!(
+        "✅Fetched metadata for {} of {} episodes ({} not found)",
+        fetched,
+        episodes.len(),
+        missing
+    );
+    Ok(())
+}
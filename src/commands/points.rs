@@ -1,23 +1,57 @@
+use crate::commands::interaction::{ephemeral, has_permission};
+use crate::commands::quote::{find_quote_question, quote_question};
+use crate::commands::team::{current_season, team_leaderboard};
+use crate::content::loaded_content;
 use anyhow::Result;
 use rand::Rng;
+use rusqlite::params;
 use rusqlite::{Connection, Statement};
 use serde::{Deserialize, Serialize};
-use serenity::all::{CommandInteraction, GuildChannel};
+use serenity::all::{
+    ChannelType, CommandInteraction, GuildChannel, GuildId, Message, Permissions, ResolvedValue,
+};
 use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseMessage};
 use serenity::prelude::*;
-use std::fs;
 
 struct Points {
     id: String,
     score: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Question {
     pub question: String,
     pub answer: String,
 }
 
+// Where trivia questions come from: questions.json, "who said it?" quotes or both
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    General,
+    Quotes,
+    Mixed,
+}
+
+impl Category {
+    pub const ALL: [Category; 3] = [Category::General, Category::Quotes, Category::Mixed];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::General => "general",
+            Category::Quotes => "quotes",
+            Category::Mixed => "mixed",
+        }
+    }
+
+    // Unknown names fall back to general questions
+    pub fn parse(name: &str) -> Category {
+        Category::ALL
+            .into_iter()
+            .find(|c| c.as_str() == name)
+            .unwrap_or(Category::General)
+    }
+}
+
 pub fn points_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
     let conn = Connection::open("points.db")?;
     let guild_id = match cmd.guild_id {
@@ -51,6 +85,12 @@ pub fn points_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteraction
             }
         }
     }
+    // Team standings are shown alongside the individual ones
+    let season = current_season(&conn, &guild_id)?;
+    let teams = team_leaderboard(&conn, &guild_id, season)?;
+    if !teams.is_empty() {
+        msg_content.push_str(&format!("\n## Teams (season {}){}", season, teams));
+    }
     let rsp_msg = CreateInteractionResponseMessage::new().content(msg_content);
     Ok(CreateInteractionResponse::Message(rsp_msg))
 }
@@ -60,17 +100,110 @@ fn get_rand_number(from: usize, to: usize) -> usize {
     rng.gen_range(from..to)
 }
 
+// The channel trivia is played in, the first text channel called general
+pub async fn find_trivia_channel(guild: GuildId, ctx: &Context) -> Result<Option<GuildChannel>> {
+    let channels = guild.channels(&ctx.http).await?;
+    Ok(channels
+        .into_values()
+        .find(|c| c.kind == ChannelType::Text && c.name == "general"))
+}
+
 // const BLACKLISTED_GUILD: u64 = 1003764837182087188;
 
-pub async fn send_trivia(channel: &GuildChannel, ctx: &Context) -> Result<()> {
-    let str = fs::read_to_string("questions.json")?;
-    let questions: Vec<Question> = serde_json::from_str(&str)?;
-    let question = &questions[get_rand_number(0, questions.len())];
+// Finds the question a bot message was asking, if any
+pub fn find_question(content: &str) -> Result<Option<Question>> {
+    if let Some(question) = find_quote_question(content)? {
+        return Ok(Some(question));
+    }
+    let questions = &loaded_content().questions;
+    Ok(questions.iter().find(|q| q.question == content).cloned())
+}
+
+// Picks a random trivia question of the category, mixed rounds toss a coin
+pub fn random_question(category: Category, guild: Option<&str>) -> Result<Question> {
+    let quotes = match category {
+        Category::General => false,
+        Category::Quotes => true,
+        Category::Mixed => rand::thread_rng().gen_bool(0.5),
+    };
+    if quotes {
+        return match quote_question(guild)? {
+            Some(body) => Ok(body),
+            None => Err(anyhow::anyhow!("There are no quotes to ask about")),
+        };
+    }
+    let questions = &loaded_content().questions;
+    if questions.is_empty() {
+        return Err(anyhow::anyhow!("questions.json has no questions"));
+    }
+    Ok(questions[get_rand_number(0, questions.len())].clone())
+}
+
+pub async fn send_trivia(
+    channel: &GuildChannel,
+    category: Category,
+    ctx: &Context,
+) -> Result<Message> {
+    let question = random_question(category, Some(&channel.guild_id.to_string()))?;
     // if channel.guild_id == BLACKLISTED_GUILD {
     //     return Ok(());
     // }
-    channel.say(&ctx.http, &question.question).await?;
-    Ok(())
+    Ok(channel.say(&ctx.http, &question.question).await?)
+}
+
+// The category of the questions regular rounds ask in the guild, general unless set
+pub fn trivia_category(guild: &str) -> Result<Category> {
+    let conn = Connection::open("points.db")?;
+    let category: Option<String> = match conn.query_row(
+        "select category from trivia_settings where guild = ?1",
+        params![guild],
+        |row| row.get(0),
+    ) {
+        Ok(body) => Some(body),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e.into()),
+    };
+    Ok(category
+        .map(|c| Category::parse(&c))
+        .unwrap_or(Category::General))
+}
+
+pub fn trivia_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let guild = match cmd.guild_id {
+        Some(body) => body.to_string(),
+        None => return Ok(ephemeral("Trivia can only be set up in a server")),
+    };
+    let mut category: Option<Category> = None;
+    for option in cmd.data.options() {
+        if let ("category", ResolvedValue::String(s)) = (option.name, option.value) {
+            category = Some(Category::parse(s));
+        }
+    }
+    let category = match category {
+        Some(body) => body,
+        None => {
+            let current = trivia_category(&guild)?;
+            return Ok(ephemeral(format!(
+                "Trivia rounds ask {} questions",
+                current.as_str()
+            )));
+        }
+    };
+    if !has_permission(cmd.member.as_deref(), Permissions::MANAGE_GUILD) {
+        return Ok(ephemeral(
+            "Only members with Manage Server can change the trivia category",
+        ));
+    }
+    let conn = Connection::open("points.db")?;
+    conn.execute(
+        "insert into trivia_settings (guild, category) values (?1, ?2)
+        on conflict(guild) do update set category = excluded.category",
+        params![guild, category.as_str()],
+    )?;
+    Ok(ephemeral(format!(
+        "Trivia rounds will ask {} questions from the next question on",
+        category.as_str()
+    )))
 }
 
 pub fn update_user_points(user: &String, guild: String) -> Result<()> {
@@ -101,3 +234,22 @@ pub fn update_user_points(user: &String, guild: String) -> Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categories_parse_their_names() {
+        for category in Category::ALL {
+            assert_eq!(Category::parse(category.as_str()), category);
+        }
+    }
+
+    #[test]
+    fn unknown_categories_are_general() {
+        assert_eq!(Category::parse(""), Category::General);
+        assert_eq!(Category::parse("Quotes"), Category::General);
+        assert_eq!(Category::parse("history"), Category::General);
+    }
+}
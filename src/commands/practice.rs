@@ -0,0 +1,116 @@
+use crate::commands::points::{find_question, random_question, Category};
+use crate::commands::quote::find_quote_question;
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::all::{ChannelId, Message};
+use serenity::builder::EditMessage;
+use serenity::prelude::*;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const PRACTICE_HELP: &str = "## Trivia practice\nSend `practice` to get a question, or `who said it` for a quote to attribute, and reply to it with your answer. Send `score` to see your practice score.\nPractice points are personal and never count towards guild leaderboards.";
+
+// Practice scores live apart from the guild points table
+fn update_practice_points(user: &str, correct: bool) -> Result<()> {
+    let conn = Connection::open("points.db")?;
+    conn.execute(
+        "insert into practice_points (user, score, answered) values (?1, ?2, 1)
+        on conflict(user) do update set score = score + excluded.score, answered = answered + 1",
+        params![user, correct as i64],
+    )?;
+    Ok(())
+}
+
+fn practice_score(user: &str) -> Result<(i64, i64)> {
+    let conn = Connection::open("points.db")?;
+    let score: Option<(i64, i64)> = conn
+        .query_row(
+            "select score, answered from practice_points where user = ?1",
+            params![user],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(score.unwrap_or((0, 0)))
+}
+
+async fn send_practice_question(
+    channel: ChannelId,
+    category: Category,
+    ctx: &Context,
+) -> Result<()> {
+    // Practice runs in DMs, so it only asks about the shared quotes
+    let question = random_question(category, None)?;
+    channel.say(&ctx.http, &question.question).await?;
+    Ok(())
+}
+
+// Handles a direct message sent to the bot
+pub async fn practice_message(ctx: &Context, msg: &Message) -> Result<()> {
+    if msg.author.bot {
+        return Ok(());
+    }
+    let client_token = std::env::var("CLIENT_ID")?;
+    let user = msg.author.id.to_string();
+
+    if let Some(msg_reply) = &msg.referenced_message {
+        if msg_reply.author.id.to_string() != client_token || !msg_reply.content.contains("**Q") {
+            return Ok(());
+        }
+        let question = match find_question(&msg_reply.content)? {
+            Some(body) => body,
+            None => return Ok(()),
+        };
+        let correct = question.answer == msg.content.trim().to_ascii_lowercase();
+        update_practice_points(&user, correct)?;
+        // Each practice question only takes one answer
+        let mut msg_reply = msg_reply.clone();
+        let new_msg = msg_reply.content.replace("**", "__");
+        msg_reply
+            .edit(&ctx.http, EditMessage::new().content(new_msg))
+            .await
+            .unwrap_or(());
+        if correct {
+            msg.reply(&ctx.http, "✅ Correct!").await?;
+        } else {
+            msg.reply(
+                &ctx.http,
+                format!("❌ Not quite, the answer was **{}**", question.answer),
+            )
+            .await?;
+        }
+        sleep(Duration::from_secs(3)).await;
+        // The next question is of the same kind as the answered one
+        let category = if find_quote_question(&question.question)?.is_some() {
+            Category::Quotes
+        } else {
+            Category::General
+        };
+        return send_practice_question(msg.channel_id, category, ctx).await;
+    }
+
+    match msg.content.trim().to_ascii_lowercase().as_str() {
+        "practice" | "trivia" | "question" => {
+            send_practice_question(msg.channel_id, Category::General, ctx).await
+        }
+        "who said it" | "who said it?" | "quotes" => {
+            send_practice_question(msg.channel_id, Category::Quotes, ctx).await
+        }
+        "score" | "points" => {
+            let (score, answered) = practice_score(&user)?;
+            msg.channel_id
+                .say(
+                    &ctx.http,
+                    format!(
+                        "Your practice score is {} out of {} answered questions",
+                        score, answered
+                    ),
+                )
+                .await?;
+            Ok(())
+        }
+        _ => {
+            msg.channel_id.say(&ctx.http, PRACTICE_HELP).await?;
+            Ok(())
+        }
+    }
+}
@@ -1,49 +1,1048 @@
-use rand::Rng;
+use crate::commands::doctor::{find_doctor, DOCTORS};
+use crate::commands::episode::{row_to_episode, search_words, Episode, EPISODE_COLUMNS};
+use crate::commands::interaction::{ephemeral, has_permission, subcommand};
+use crate::commands::points::Question;
+use anyhow::Result;
+use chrono::{NaiveTime, Utc};
+use rand::seq::SliceRandom;
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
-use serenity::builder::{CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage};
-use std::fs;
+use serenity::all::{
+    ActionRowComponent, ButtonStyle, ChannelId, CommandInteraction, ComponentInteraction,
+    InputTextStyle, ModalInteraction, Permissions, ResolvedValue,
+};
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter, CreateInputText,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, CreateModal,
+};
+use serenity::model::application::ResolvedOption;
+use serenity::prelude::*;
 
+const PAGE_SIZE: usize = 5;
+// Room for the results of a search page, Discord messages hold 2000 characters and the
+// heading with the search text takes up to about 130
+const PAGE_CHARS: usize = 1850;
+// First line of "who said it?" questions, followed by the quote ID. The **Q marks them as
+// trivia like questions.json.
+const WHO_SAID_IT: &str = "**Q** Who said it?";
+const CHOICES: [&str; 4] = ["a", "b", "c", "d"];
+// Longest /quote search text, so it still fits in the page buttons' custom ID
+pub const MAX_SEARCH_LEN: u16 = 80;
+
+// A quote as written in quotes.json. The author is the speaker; the Doctor is worked
+// out from it when not given, and the episode is a catalog title.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct Quote {
+pub struct Quote {
+    pub quote: String,
+    pub author: String,
+    // Doctor ID the quote belongs to, e.g. "tenth"
+    #[serde(default)]
+    pub doctor: Option<String>,
+    #[serde(default)]
+    pub episode: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Member who submitted the quote with /quotes add
+    #[serde(default)]
+    pub submitted_by: Option<String>,
+}
+
+impl Quote {
+    // Doctor of the quote, given or recognised from the speaker
+    pub fn doctor_id(&self) -> Option<String> {
+        match &self.doctor {
+            Some(doctor) => find_doctor(doctor).map(|d| d.id.to_string()),
+            None => find_doctor(&self.author).map(|d| d.id.to_string()),
+        }
+    }
+}
+
+// Quotes of quotes.json are shared by every guild, approved submissions only belong to the
+// guild they were submitted in. The guild is bound to the given parameter number.
+fn in_guild(param: usize) -> String {
+    format!("(guild is null or guild = ?{})", param)
+}
+
+// Columns read by row_to_quote, in order
+pub const QUOTE_COLUMNS: &str = "quote, speaker, doctor, episode, tags, submitted_by";
+
+pub fn row_to_quote(row: &rusqlite::Row) -> rusqlite::Result<Quote> {
+    let tags: String = row.get(4)?;
+    Ok(Quote {
+        quote: row.get(0)?,
+        author: row.get(1)?,
+        doctor: row.get(2)?,
+        episode: row.get(3)?,
+        tags: tags
+            .split(',')
+            .filter(|t| !t.is_empty())
+            .map(String::from)
+            .collect(),
+        submitted_by: row.get(5)?,
+    })
+}
+
+// Adds or updates a quote of quotes.json in the store, quotes are unique by their text. A
+// submitted quote the file also lists becomes a shared file quote, keeping its credit.
+pub fn upsert_quote(conn: &Connection, quote: &Quote) -> Result<()> {
+    // The oldest guild's copy of a submitted quote becomes the shared one, keeping its ID
+    // and credit, the copies of other guilds are dropped
+    conn.execute(
+        "update quotes set guild = null
+        where id = (select min(id) from quotes where quote = ?1 and guild is not null)
+        and not exists (select 1 from quotes where quote = ?1 and guild is null)",
+        params![quote.quote.trim()],
+    )?;
+    conn.execute(
+        "delete from quotes where quote = ?1 and guild is not null",
+        params![quote.quote.trim()],
+    )?;
+    conn.execute(
+        "insert into quotes (quote, speaker, doctor, episode, tags, source, submitted_by)
+        values (?1, ?2, ?3, ?4, ?5, 'file', ?6)
+        on conflict(quote, ifnull(guild, '')) do update set speaker = excluded.speaker,
+            doctor = excluded.doctor, episode = excluded.episode, tags = excluded.tags,
+            source = 'file', submitted_by = coalesce(excluded.submitted_by, quotes.submitted_by)",
+        params![
+            quote.quote.trim(),
+            quote.author.trim(),
+            quote.doctor_id(),
+            quote.episode,
+            quote.tags.join(","),
+            quote.submitted_by
+        ],
+    )?;
+    Ok(())
+}
+
+// Syncs the quotes of quotes.json into the store, skipped when the file is unchanged
+// since the last sync. Quotes removed from the file are dropped, submitted ones are kept.
+pub fn sync_quotes(conn: &mut Connection, quotes: &[Quote], hash: &str) -> Result<()> {
+    let synced: Option<String> = conn
+        .query_row(
+            "select value from catalog_meta where key = 'quotes_hash'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if synced.as_deref() == Some(hash) {
+        return Ok(());
+    }
+    let tx = conn.transaction()?;
+    for quote in quotes {
+        upsert_quote(&tx, quote)?;
+    }
+    // Quotes keep their IDs across syncs, the quote of the day history refers to them
+    let texts: Vec<&str> = quotes.iter().map(|q| q.quote.trim()).collect();
+    tx.execute(
+        "delete from quotes where source = 'file' and quote not in (select value from json_each(?1))",
+        params![serde_json::to_string(&texts)?],
+    )?;
+    tx.execute(
+        "insert into catalog_meta (key, value) values ('quotes_hash', ?1)
+        on conflict(key) do update set value = excluded.value",
+        params![hash],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Catalog episode a quote cites, when the title is in the catalog
+pub fn quote_episode(conn: &Connection, quote: &Quote) -> Result<Option<Episode>> {
+    let title = match &quote.episode {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    let episode = conn
+        .query_row(
+            &format!(
+                "select {} from episodes where title = ?1 collate nocase
+                order by era, season, episode, part limit 1",
+                EPISODE_COLUMNS
+            ),
+            params![title],
+            row_to_episode,
+        )
+        .optional()?;
+    Ok(episode)
+}
+
+// Embed of a quote, the footer cites the episode it comes from and credits the member
+// who submitted it
+pub fn quote_embed(conn: &Connection, quote: &Quote) -> Result<CreateEmbed> {
+    let embed = CreateEmbed::new()
+        .title(&quote.author)
+        .description(&quote.quote);
+    let mut footer: Vec<String> = Vec::new();
+    match (quote_episode(conn, quote)?, &quote.episode) {
+        (Some(episode), _) => {
+            footer.push(format!("{} ({})", episode.full_title(), episode.number()))
+        }
+        (None, Some(title)) => footer.push(title.clone()),
+        (None, None) => {}
+    };
+    if let Some(name) = &quote.submitted_by {
+        footer.push(format!("Submitted by {}", name));
+    }
+    if footer.is_empty() {
+        return Ok(embed);
+    }
+    Ok(embed.footer(CreateEmbedFooter::new(footer.join(" · "))))
+}
+
+// A random quote of the given Doctor
+pub fn quote_for_doctor(doctor: &str, guild: Option<&str>) -> Result<Option<Quote>> {
+    let conn = Connection::open("episodes.db")?;
+    let quote = conn
+        .query_row(
+            &format!(
+                "select {} from quotes where doctor = ?1 and {} order by random() limit 1",
+                QUOTE_COLUMNS,
+                in_guild(2)
+            ),
+            params![doctor, guild],
+            row_to_quote,
+        )
+        .optional()?;
+    Ok(quote)
+}
+
+// Builds a "who said it?" trivia question from a random quote. The wrong choices are
+// speakers of other quotes and other Doctors.
+pub fn quote_question(guild: Option<&str>) -> Result<Option<Question>> {
+    let conn = Connection::open("episodes.db")?;
+    let quote = conn
+        .query_row(
+            &format!(
+                "select {}, id from quotes where {} order by random() limit 1",
+                QUOTE_COLUMNS,
+                in_guild(1)
+            ),
+            params![guild],
+            |row| Ok((row.get::<_, i64>(6)?, row_to_quote(row)?)),
+        )
+        .optional()?;
+    let (id, quote) = match quote {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    let mut stmt = conn.prepare(&format!(
+        "select distinct speaker from quotes where {}",
+        in_guild(1)
+    ))?;
+    let speakers = stmt
+        .query_map(params![guild], |row| row.get(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+    let mut wrong = wrong_choices(&quote, speakers);
+    let mut rng = rand::thread_rng();
+    wrong.shuffle(&mut rng);
+    let mut choices: Vec<String> = wrong.into_iter().take(CHOICES.len() - 1).collect();
+    choices.push(quote.author.clone());
+    choices.shuffle(&mut rng);
+    let question = who_said_it(id, &quote.quote, &choices);
+    let answer = match answer_letter(&question, &quote.author) {
+        Some(body) => body,
+        None => return Err(anyhow::anyhow!("The speaker is missing from the choices")),
+    };
+    Ok(Some(Question { question, answer }))
+}
+
+// Speakers and Doctors that didn't say the quote. Other names of the speaking Doctor, e.g.
+// "Tenth Doctor" for "The Tenth Doctor", would be right answers too and are left out.
+fn wrong_choices(quote: &Quote, speakers: Vec<String>) -> Vec<String> {
+    let doctor = quote.doctor_id();
+    let mut wrong: Vec<String> = Vec::new();
+    let doctors = DOCTORS.iter().map(|d| format!("The {}", d.name));
+    for speaker in speakers.into_iter().chain(doctors) {
+        let same_doctor =
+            doctor.is_some() && find_doctor(&speaker).map(|d| d.id.to_string()) == doctor;
+        if !speaker.eq_ignore_ascii_case(&quote.author)
+            && !same_doctor
+            && !wrong.iter().any(|w| w.eq_ignore_ascii_case(&speaker))
+        {
+            wrong.push(speaker);
+        }
+    }
+    wrong
+}
+
+// Text of a "who said it?" question, every line of the quote is quoted
+fn who_said_it(id: i64, quote: &str, choices: &[String]) -> String {
+    let mut question = format!("{} (#{})", WHO_SAID_IT, id);
+    for line in quote.lines() {
+        question.push_str(&format!("\n> {}", line));
+    }
+    for (letter, choice) in CHOICES.iter().zip(choices) {
+        question.push_str(&format!("\n{}.{}", letter, choice));
+    }
+    question
+}
+
+// Quote ID of a "who said it?" question
+fn question_quote_id(content: &str) -> Option<i64> {
+    content
+        .lines()
+        .next()?
+        .strip_prefix(WHO_SAID_IT)?
+        .trim()
+        .strip_prefix("(#")?
+        .strip_suffix(')')?
+        .parse()
+        .ok()
+}
+
+// Letter of the choice naming the speaker
+fn answer_letter(content: &str, speaker: &str) -> Option<String> {
+    content.lines().find_map(|line| match line.split_once('.') {
+        Some((letter, choice)) if CHOICES.contains(&letter) && choice == speaker => {
+            Some(letter.to_string())
+        }
+        _ => None,
+    })
+}
+
+// Recognises a "who said it?" question the bot sent and works out its answer from the
+// quote store, so these questions don't need to be kept anywhere
+pub fn find_quote_question(content: &str) -> Result<Option<Question>> {
+    let id = match question_quote_id(content) {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    let conn = Connection::open("episodes.db")?;
+    let speaker: Option<String> = conn
+        .query_row(
+            "select speaker from quotes where id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let speaker = match speaker {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    Ok(answer_letter(content, &speaker).map(|answer| Question {
+        question: content.to_string(),
+        answer,
+    }))
+}
+
+fn fts_quotes(conn: &Connection, query: &str, guild: Option<&str>) -> Result<Vec<Quote>> {
+    let mut stmt = conn.prepare(&format!(
+        "select q.quote, q.speaker, q.doctor, q.episode, q.tags, q.submitted_by from quotes_fts
+        join quotes q on q.id = quotes_fts.rowid
+        where quotes_fts match ?1 and {}
+        order by bm25(quotes_fts), q.id",
+        in_guild(2)
+    ))?;
+    let quotes = stmt
+        .query_map(params![query, guild], row_to_quote)?
+        .collect::<Result<Vec<Quote>, rusqlite::Error>>()?;
+    Ok(quotes)
+}
+
+// Ranked full-text search over quotes, speakers, episodes and tags: all words as
+// prefixes, then any word
+pub fn search_quotes(conn: &Connection, text: &str, guild: Option<&str>) -> Result<Vec<Quote>> {
+    let words = search_words(text);
+    if words.is_empty() {
+        return Ok(Vec::new());
+    }
+    let terms: Vec<String> = words.iter().map(|w| format!("\"{}\"*", w)).collect();
+    let quotes = fts_quotes(conn, &terms.join(" "), guild)?;
+    if !quotes.is_empty() || words.len() == 1 {
+        return Ok(quotes);
+    }
+    fts_quotes(conn, &terms.join(" OR "), guild)
+}
+
+fn search_line(quote: &Quote) -> String {
+    let line = match &quote.episode {
+        Some(episode) => format!("> {}\n— **{}**, *{}*\n", quote.quote, quote.author, episode),
+        None => format!("> {}\n— **{}**\n", quote.quote, quote.author),
+    };
+    // A quote that fills a page on its own is cut short
+    if line.chars().count() > PAGE_CHARS {
+        let mut cut: String = line.chars().take(PAGE_CHARS - 2).collect();
+        cut.push_str("…\n");
+        return cut;
+    }
+    line
+}
+
+// Splits result lines into pages of at most PAGE_SIZE quotes and PAGE_CHARS characters,
+// returning the index each page starts at
+fn page_starts(lines: &[String]) -> Vec<usize> {
+    let mut starts: Vec<usize> = Vec::new();
+    let mut chars = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let len = line.chars().count();
+        let full = starts
+            .last()
+            .map(|start| i - start >= PAGE_SIZE || chars + len > PAGE_CHARS)
+            .unwrap_or(true);
+        if full {
+            starts.push(i);
+            chars = 0;
+        }
+        chars += len;
+    }
+    starts
+}
+
+// One page of search results, the page buttons carry the search text
+fn search_page(
+    text: &str,
+    page: usize,
+    guild: Option<&str>,
+) -> Result<CreateInteractionResponseMessage> {
+    let conn = Connection::open("episodes.db")?;
+    let quotes = search_quotes(&conn, text, guild)?;
+    if quotes.is_empty() {
+        return Ok(CreateInteractionResponseMessage::new()
+            .content(format!("No quotes found for {}", text))
+            .components(Vec::new()));
+    }
+    let lines: Vec<String> = quotes.iter().map(search_line).collect();
+    let starts = page_starts(&lines);
+    let pages = starts.len();
+    let page = page.min(pages - 1);
+    let end = starts.get(page + 1).copied().unwrap_or(lines.len());
+    let mut msg_content = format!(
+        "## Quotes matching \"{}\" (page {}/{})\n",
+        text,
+        page + 1,
+        pages
+    );
+    for line in &lines[starts[page]..end] {
+        msg_content.push_str(line);
+    }
+    let buttons = vec![
+        CreateButton::new(format!("quote:search:{}:{}", page.saturating_sub(1), text))
+            .label("◀ Previous")
+            .style(ButtonStyle::Secondary)
+            .disabled(page == 0),
+        CreateButton::new(format!("quote:search:{}:{}", page + 1, text))
+            .label("Next ▶")
+            .style(ButtonStyle::Secondary)
+            .disabled(page + 1 >= pages),
+    ];
+    Ok(CreateInteractionResponseMessage::new()
+        .content(msg_content)
+        .components(vec![CreateActionRow::Buttons(buttons)]))
+}
+
+// Handles the page buttons of /quote search and the review buttons of /quotes queue
+pub fn quote_button_response(
+    component: &ComponentInteraction,
+) -> Result<CreateInteractionResponse> {
+    let guild = component.guild_id.map(|g| g.to_string());
+    let parts: Vec<&str> = component.data.custom_id.splitn(4, ':').collect();
+    match parts[..] {
+        [_, "search", page, text] => Ok(CreateInteractionResponse::UpdateMessage(search_page(
+            text,
+            page.parse::<usize>()?,
+            guild.as_deref(),
+        )?)),
+        [_, decision @ ("approve" | "reject"), id] => {
+            review_submission(component, decision, id.parse::<i64>()?)
+        }
+        _ => Err(anyhow::anyhow!("Failed to parse quote button")),
+    }
+}
+
+// A random quote matching the optional Doctor, speaker and keyword filters
+fn random_quote(
+    options: &[ResolvedOption],
+    guild: Option<&str>,
+) -> Result<CreateInteractionResponse> {
+    let mut doctor: Option<String> = None;
+    let mut speaker: Option<String> = None;
+    let mut keyword: Option<String> = None;
+    for option in options {
+        match (option.name, &option.value) {
+            ("doctor", ResolvedValue::String(s)) => {
+                match find_doctor(s) {
+                    Some(d) => doctor = Some(d.id.to_string()),
+                    None => {
+                        return Ok(CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::new()
+                                .content(format!("There is no Doctor called {}", s)),
+                        ))
+                    }
+                };
+            }
+            ("speaker", ResolvedValue::String(s)) => speaker = Some(format!("%{}%", s.trim())),
+            ("keyword", ResolvedValue::String(s)) => keyword = Some(format!("%{}%", s.trim())),
+            _ => {}
+        }
+    }
+    let conn = Connection::open("episodes.db")?;
+    let quote = conn
+        .query_row(
+            &format!(
+                "select {} from quotes
+                where (?1 is null or doctor = ?1)
+                and (?2 is null or speaker like ?2)
+                and (?3 is null or quote like ?3 or tags like ?3 or episode like ?3)
+                and {}
+                order by random() limit 1",
+                QUOTE_COLUMNS,
+                in_guild(4)
+            ),
+            params![doctor, speaker, keyword, guild],
+            row_to_quote,
+        )
+        .optional()?;
+    let rsp = match quote {
+        Some(quote) => {
+            CreateInteractionResponseMessage::new().add_embed(quote_embed(&conn, &quote)?)
+        }
+        None => CreateInteractionResponseMessage::new().content("No quotes match these filters"),
+    };
+    Ok(CreateInteractionResponse::Message(rsp))
+}
+
+// Handles /quote, a search lists every match and otherwise a random quote is sent
+pub fn quote_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let guild = cmd.guild_id.map(|g| g.to_string());
+    let options = cmd.data.options();
+    let search = options.iter().find_map(|o| match o.value {
+        ResolvedValue::String(s) if o.name == "search" => Some(s.trim().to_string()),
+        _ => None,
+    });
+    if let Some(text) = search {
+        return Ok(CreateInteractionResponse::Message(search_page(
+            &text,
+            0,
+            guild.as_deref(),
+        )?));
+    }
+    random_quote(&options, guild.as_deref())
+}
+
+// Handles /quotes add|queue, submitting quotes and reviewing them
+pub fn quotes_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let (subcmd, _) = subcommand(cmd, "quotes")?;
+    let guild = match cmd.guild_id {
+        Some(body) => body.to_string(),
+        None => {
+            return Ok(ephemeral(
+                "Quotes can only be submitted and reviewed in a server",
+            ))
+        }
+    };
+    if subcmd == "add" {
+        return Ok(submission_modal());
+    }
+    if !has_permission(cmd.member.as_deref(), Permissions::MANAGE_MESSAGES) {
+        return Ok(ephemeral(
+            "Only members with Manage Messages can review submitted quotes",
+        ));
+    }
+    Ok(CreateInteractionResponse::Message(
+        queue_page(&guild, None)?.ephemeral(true),
+    ))
+}
+
+// Longest quote members can submit
+const MAX_SUBMISSION_LEN: u16 = 500;
+const MAX_SPEAKER_LEN: u16 = 100;
+
+// A quote a member submitted, waiting in the guild's review queue
+struct Submission {
+    id: i64,
     quote: String,
-    author: String,
-}
-
-pub fn quote_cmd_response() -> CreateInteractionResponse {
-    let dir = fs::read_to_string("quotes.json");
-    let mut quote: Quote;
-    // Default quote in case the file quotes.json does not exist
-    quote = Quote {
-        quote: String::from("quote_not_found"),
-        author: String::from("author_not_found"),
-    };
-    let quotes: Vec<Quote>;
-    match dir {
-        Ok(body) => {
-            let ok = serde_json::from_str(&body);
-            quotes = match ok {
-                Ok(body) => body,
-                Err(_) => {
-                    println!("Failed to parse quotes.json");
-                    let rsp_embed = CreateEmbed::new()
-                        .title(quote.author)
-                        .description(quote.quote);
-                    let rsp = CreateInteractionResponseMessage::new().add_embed(rsp_embed);
-                    return CreateInteractionResponse::Message(rsp);
+    speaker: String,
+    episode: Option<String>,
+    user: String,
+    name: String,
+}
+
+fn submission_modal() -> CreateInteractionResponse {
+    let fields = vec![
+        CreateInputText::new(InputTextStyle::Paragraph, "Quote", "quote")
+            .max_length(MAX_SUBMISSION_LEN)
+            .required(true),
+        CreateInputText::new(InputTextStyle::Short, "Who said it", "speaker")
+            .placeholder("The Tenth Doctor")
+            .max_length(MAX_SPEAKER_LEN)
+            .required(true),
+        CreateInputText::new(InputTextStyle::Short, "Episode", "episode")
+            .placeholder("Blink")
+            .max_length(MAX_SPEAKER_LEN)
+            .required(false),
+    ];
+    CreateInteractionResponse::Modal(
+        CreateModal::new("quote:add", "Submit a quote")
+            .components(fields.into_iter().map(CreateActionRow::InputText).collect()),
+    )
+}
+
+// Handles the /quotes add modal, the quote waits in the queue until a moderator reviews it
+pub fn quote_modal_response(modal: &ModalInteraction) -> Result<CreateInteractionResponse> {
+    let guild = match modal.guild_id {
+        Some(body) => body.to_string(),
+        None => return Ok(ephemeral("Quotes can only be submitted in a server")),
+    };
+    let mut quote = String::new();
+    let mut speaker = String::new();
+    let mut episode: Option<String> = None;
+    for row in &modal.data.components {
+        for component in &row.components {
+            if let ActionRowComponent::InputText(input) = component {
+                let value = input
+                    .value
+                    .as_deref()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string();
+                match input.custom_id.as_str() {
+                    "quote" => quote = value,
+                    "speaker" => speaker = value,
+                    "episode" if !value.is_empty() => episode = Some(value),
+                    _ => {}
                 }
-            };
-            let mut rng = rand::thread_rng();
-            let n1 = rng.gen_range(0..quotes.len());
-            quote = quotes[n1].clone();
+            }
+        }
+    }
+    if quote.is_empty() || speaker.is_empty() {
+        return Ok(ephemeral("A quote needs its text and who said it"));
+    }
+    let conn = Connection::open("episodes.db")?;
+    let known: Option<i64> = conn
+        .query_row(
+            &format!(
+                "select id from quotes where quote = ?1 and {}
+                union select id from quote_submissions where guild = ?2 and quote = ?1 and status = 'pending'",
+                in_guild(2)
+            ),
+            params![quote, guild],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if known.is_some() {
+        return Ok(ephemeral(
+            "This quote is already collected or waiting for review",
+        ));
+    }
+    conn.execute(
+        "insert into quote_submissions (guild, quote, speaker, episode, user, name, status, submitted_at)
+        values (?1, ?2, ?3, ?4, ?5, ?6, 'pending', datetime('now'))",
+        params![
+            guild,
+            quote,
+            speaker,
+            episode,
+            modal.user.id.to_string(),
+            modal.user.name
+        ],
+    )?;
+    Ok(ephemeral(
+        "Thanks! Your quote was sent to the moderators and you will be credited once it's approved",
+    ))
+}
+
+// Oldest submission waiting for review in the guild, with approve and reject buttons
+fn queue_page(guild: &str, notice: Option<String>) -> Result<CreateInteractionResponseMessage> {
+    let conn = Connection::open("episodes.db")?;
+    let pending: i64 = conn.query_row(
+        "select count(*) from quote_submissions where guild = ?1 and status = 'pending'",
+        params![guild],
+        |row| row.get(0),
+    )?;
+    let submission = conn
+        .query_row(
+            "select id, quote, speaker, episode, user, name from quote_submissions
+            where guild = ?1 and status = 'pending' order by id limit 1",
+            params![guild],
+            |row| {
+                Ok(Submission {
+                    id: row.get(0)?,
+                    quote: row.get(1)?,
+                    speaker: row.get(2)?,
+                    episode: row.get(3)?,
+                    user: row.get(4)?,
+                    name: row.get(5)?,
+                })
+            },
+        )
+        .optional()?;
+    let notice = notice.map(|n| format!("{}\n", n)).unwrap_or_default();
+    let submission = match submission {
+        Some(body) => body,
+        None => {
+            return Ok(CreateInteractionResponseMessage::new()
+                .content(format!("{}No quotes are waiting for review", notice))
+                .embeds(Vec::new())
+                .components(Vec::new()))
         }
+    };
+    let mut embed = CreateEmbed::new()
+        .title(&submission.speaker)
+        .description(&submission.quote)
+        .field("Submitted by", format!("<@{}>", submission.user), true)
+        .footer(CreateEmbedFooter::new(format!(
+            "{} waiting for review",
+            pending
+        )));
+    if let Some(episode) = &submission.episode {
+        embed = embed.field("Episode", episode, true);
+    }
+    let buttons = vec![
+        CreateButton::new(format!("quote:approve:{}", submission.id))
+            .label("Approve")
+            .style(ButtonStyle::Success),
+        CreateButton::new(format!("quote:reject:{}", submission.id))
+            .label("Reject")
+            .style(ButtonStyle::Danger),
+    ];
+    Ok(CreateInteractionResponseMessage::new()
+        .content(format!("{}## Submitted quotes", notice))
+        .embeds(vec![embed])
+        .components(vec![CreateActionRow::Buttons(buttons)]))
+}
+
+// Approves or rejects a submission and moves on to the next one. Approved quotes join the
+// guild's quotes crediting their submitter, unless the text was collected in the meantime.
+fn review_submission(
+    component: &ComponentInteraction,
+    decision: &str,
+    id: i64,
+) -> Result<CreateInteractionResponse> {
+    let guild = match component.guild_id {
+        Some(body) => body.to_string(),
+        None => return Err(anyhow::anyhow!("Quote review outside of a guild")),
+    };
+    if !has_permission(component.member.as_ref(), Permissions::MANAGE_MESSAGES) {
+        return Ok(ephemeral(
+            "Only members with Manage Messages can review submitted quotes",
+        ));
+    }
+    let conn = Connection::open("episodes.db")?;
+    let submission = conn
+        .query_row(
+            "select id, quote, speaker, episode, user, name from quote_submissions
+            where id = ?1 and guild = ?2 and status = 'pending'",
+            params![id, guild],
+            |row| {
+                Ok(Submission {
+                    id: row.get(0)?,
+                    quote: row.get(1)?,
+                    speaker: row.get(2)?,
+                    episode: row.get(3)?,
+                    user: row.get(4)?,
+                    name: row.get(5)?,
+                })
+            },
+        )
+        .optional()?;
+    // Another moderator may have reviewed it first
+    let submission = match submission {
+        Some(body) => body,
+        None => {
+            return Ok(CreateInteractionResponse::UpdateMessage(queue_page(
+                &guild,
+                Some(String::from("That quote was already reviewed")),
+            )?))
+        }
+    };
+    let known: Option<i64> = conn
+        .query_row(
+            &format!("select id from quotes where quote = ?1 and {}", in_guild(2)),
+            params![submission.quote, guild],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let status = if decision == "approve" && known.is_none() {
+        let quote = Quote {
+            quote: submission.quote.clone(),
+            author: submission.speaker.clone(),
+            doctor: None,
+            episode: submission.episode.clone(),
+            tags: Vec::new(),
+            submitted_by: Some(submission.name.clone()),
+        };
+        conn.execute(
+            "insert into quotes (quote, speaker, doctor, episode, tags, source, submitted_by, guild)
+            values (?1, ?2, ?3, ?4, '', 'submitted', ?5, ?6)",
+            params![
+                quote.quote,
+                quote.author,
+                quote.doctor_id(),
+                quote.episode,
+                quote.submitted_by,
+                guild
+            ],
+        )?;
+        "approved"
+    } else {
+        "rejected"
+    };
+    conn.execute(
+        "update quote_submissions set status = ?1, reviewed_by = ?2, reviewed_at = datetime('now')
+        where id = ?3",
+        params![status, component.user.id.to_string(), submission.id],
+    )?;
+    let notice = match (status, decision) {
+        ("approved", _) => format!("✅ Approved the quote submitted by <@{}>", submission.user),
+        (_, "approve") => format!(
+            "❌ Rejected the quote submitted by <@{}>, it is already collected",
+            submission.user
+        ),
+        _ => format!("❌ Rejected the quote submitted by <@{}>", submission.user),
+    };
+    Ok(CreateInteractionResponse::UpdateMessage(queue_page(
+        &guild,
+        Some(notice),
+    )?))
+}
+
+// Handles /quoteoftheday set|off, times are UTC like the daily challenge
+pub fn quoteoftheday_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let guild = match cmd.guild_id {
+        Some(body) => body.to_string(),
+        None => {
+            return Ok(ephemeral(
+                "The quote of the day can only be set up in a server",
+            ))
+        }
+    };
+    if !has_permission(cmd.member.as_deref(), Permissions::MANAGE_GUILD) {
+        return Ok(ephemeral(
+            "Only members with Manage Server can set up the quote of the day",
+        ));
+    }
+    let (subcmd, sub_options) = subcommand(cmd, "quoteoftheday")?;
+    let conn = Connection::open("episodes.db")?;
+    if subcmd == "off" {
+        let removed = conn.execute(
+            "delete from quote_schedules where guild = ?1",
+            params![guild],
+        )?;
+        return Ok(ephemeral(if removed > 0 {
+            "The quote of the day is turned off"
+        } else {
+            "The quote of the day wasn't set up"
+        }));
+    }
+    let mut channel: Option<ChannelId> = None;
+    let mut time = String::new();
+    for option in sub_options {
+        match (option.name, option.value) {
+            ("channel", ResolvedValue::Channel(c)) => channel = Some(c.id),
+            ("time", ResolvedValue::String(s)) => time = s.trim().to_string(),
+            _ => {}
+        }
+    }
+    let channel = match channel {
+        Some(body) => body,
+        None => return Err(anyhow::anyhow!("Failed to parse quoteoftheday channel")),
+    };
+    let time = match NaiveTime::parse_from_str(&time, "%H:%M") {
+        Ok(body) => body.format("%H:%M").to_string(),
         Err(_) => {
-            println!("Quotes file not found");
+            return Ok(ephemeral(format!(
+                "{} isn't a time, use HH:MM in UTC",
+                time
+            )))
+        }
+    };
+    // A new time applies from today, unless today's quote was already posted
+    conn.execute(
+        "insert into quote_schedules (guild, channel, time) values (?1, ?2, ?3)
+        on conflict(guild) do update set channel = excluded.channel, time = excluded.time",
+        params![guild, channel.to_string(), time],
+    )?;
+    Ok(ephemeral(format!(
+        "The quote of the day will be posted in <#{}> every day at {} UTC",
+        channel, time
+    )))
+}
+
+// Picks a quote the guild hasn't been sent yet, starting over once all were sent
+fn next_quote(conn: &Connection, guild: &str) -> Result<Option<(i64, Quote)>> {
+    for _ in 0..2 {
+        let id: Option<i64> = conn
+            .query_row(
+                &format!(
+                    "select id from quotes where {}
+                    and id not in (select quote_id from quote_history where guild = ?1)
+                    order by random() limit 1",
+                    in_guild(1)
+                ),
+                params![guild],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = id {
+            let quote = conn.query_row(
+                &format!("select {} from quotes where id = ?1", QUOTE_COLUMNS),
+                params![id],
+                row_to_quote,
+            )?;
+            return Ok(Some((id, quote)));
         }
+        conn.execute("delete from quote_history where guild = ?1", params![guild])?;
+    }
+    Ok(None)
+}
+
+// Posts the quote of the day of every guild whose time has come and that wasn't
+// served yet today
+pub async fn post_quotes_of_the_day(ctx: &Context) -> Result<()> {
+    let now = Utc::now();
+    let today = now.date_naive().to_string();
+    let time = now.format("%H:%M").to_string();
+    let due: Vec<(String, String)> = {
+        let conn = Connection::open("episodes.db")?;
+        let mut stmt = conn.prepare(
+            "select guild, channel from quote_schedules
+            where time <= ?1 and (last_posted is null or last_posted < ?2)",
+        )?;
+        let due = stmt
+            .query_map(params![time, today], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()?;
+        due
+    };
+    for (guild, channel) in due {
+        match post_quote_of_the_day(&guild, &channel, ctx).await {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Failed to post the quote of the day in {}: {}", channel, e);
+            }
+        }
+        // Marked as done either way, a missing channel shouldn't be retried every minute
+        let marked = Connection::open("episodes.db").and_then(|conn| {
+            conn.execute(
+                "update quote_schedules set last_posted = ?1 where guild = ?2",
+                params![today, guild],
+            )
+        });
+        match marked {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Failed to mark the quote of the day of {}: {}", guild, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn post_quote_of_the_day(guild: &str, channel: &str, ctx: &Context) -> Result<()> {
+    let (id, message) = {
+        let conn = Connection::open("episodes.db")?;
+        let (id, quote) = match next_quote(&conn, guild)? {
+            Some(body) => body,
+            None => return Ok(()),
+        };
+        let embed = quote_embed(&conn, &quote)?;
+        (
+            id,
+            CreateMessage::new()
+                .content("## Quote of the day")
+                .embed(embed),
+        )
+    };
+    let channel = ChannelId::new(channel.parse()?);
+    channel.send_message(&ctx.http, message).await?;
+    let conn = Connection::open("episodes.db")?;
+    conn.execute(
+        "insert or ignore into quote_history (guild, quote_id, posted_at)
+        values (?1, ?2, datetime('now'))",
+        params![guild, id],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_hold_at_most_page_size_quotes() {
+        let lines = vec![String::from("> Allons-y!\n— **The Tenth Doctor**\n"); 12];
+        assert_eq!(page_starts(&lines), vec![0, 5, 10]);
+    }
+
+    #[test]
+    fn long_quotes_start_a_new_page_before_the_limit() {
+        let lines = vec!["x".repeat(600); 5];
+        assert_eq!(page_starts(&lines), vec![0, 3]);
+        let page: usize = lines[0..3].iter().map(|l| l.chars().count()).sum();
+        assert!(page <= PAGE_CHARS);
+    }
+
+    #[test]
+    fn a_quote_longer_than_a_page_is_cut() {
+        let quote = Quote {
+            quote: "a".repeat(3000),
+            author: String::from("River Song"),
+            doctor: None,
+            episode: None,
+            tags: Vec::new(),
+            submitted_by: None,
+        };
+        assert_eq!(search_line(&quote).chars().count(), PAGE_CHARS);
+    }
+
+    fn choices() -> Vec<String> {
+        [
+            "River Song",
+            "The Tenth Doctor",
+            "Rory Williams",
+            "Amy Pond",
+        ]
+        .iter()
+        .map(|c| c.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn multi_line_quotes_keep_their_question_id() {
+        let question = who_said_it(42, "Hello, sweetie.\nSpoilers!", &choices());
+        assert_eq!(
+            question,
+            "**Q** Who said it? (#42)\n> Hello, sweetie.\n> Spoilers!\na.River Song\nb.The Tenth Doctor\nc.Rory Williams\nd.Amy Pond"
+        );
+        assert_eq!(question_quote_id(&question), Some(42));
+        assert_eq!(answer_letter(&question, "River Song").as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn other_names_of_the_speaking_doctor_are_not_wrong_choices() {
+        let quote = Quote {
+            quote: String::from("Allons-y!"),
+            author: String::from("The Tenth Doctor"),
+            doctor: None,
+            episode: None,
+            tags: Vec::new(),
+            submitted_by: None,
+        };
+        let speakers = vec![String::from("Tenth Doctor"), String::from("Rose Tyler")];
+        let wrong = wrong_choices(&quote, speakers);
+        assert!(wrong.contains(&String::from("Rose Tyler")));
+        assert!(wrong.contains(&String::from("The Ninth Doctor")));
+        assert!(!wrong
+            .iter()
+            .any(|w| find_doctor(w).map(|d| d.id) == Some("tenth")));
+    }
+
+    #[test]
+    fn quoted_lines_are_not_taken_for_choices() {
+        let question = who_said_it(7, "a.Amy Pond", &choices());
+        assert_eq!(answer_letter(&question, "Amy Pond").as_deref(), Some("d"));
+    }
+
+    #[test]
+    fn other_messages_are_not_quote_questions() {
+        assert_eq!(question_quote_id("**Q** Who said it?\n> Allons-y!"), None);
+        assert_eq!(question_quote_id("**Q** What is a TARDIS?\na.A ship"), None);
+        assert_eq!(question_quote_id("**Q** Who said it? (#x)"), None);
+    }
+
+    #[test]
+    fn trivia_questions_are_not_looked_up_as_quotes() {
+        let question = "**Q** What is the Doctor's home planet?\na.Gallifrey\nb.Skaro";
+        assert!(find_quote_question(question).unwrap().is_none());
     }
-    // Creare embed quote
-    let rsp_embed = CreateEmbed::new()
-        .title(quote.author)
-        .description(quote.quote);
-    let rsp = CreateInteractionResponseMessage::new().add_embed(rsp_embed);
-    CreateInteractionResponse::Message(rsp)
 }
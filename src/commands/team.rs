@@ -0,0 +1,203 @@
+use crate::commands::interaction::{has_permission, message, subcommand};
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::all::{CommandInteraction, Permissions, ResolvedValue, RoleId};
+use serenity::builder::CreateInteractionResponse;
+
+// Season currently being played in the guild, seasons start at 1
+pub fn current_season(conn: &Connection, guild: &str) -> Result<i64> {
+    let season: Option<i64> = conn
+        .query_row(
+            "select season from team_seasons where guild = ?1",
+            params![guild],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(season.unwrap_or(1))
+}
+
+// Finds the team of a user, explicit membership wins over role based teams
+pub fn find_team(
+    conn: &Connection,
+    guild: &str,
+    user: &str,
+    roles: &[RoleId],
+) -> Result<Option<String>> {
+    let joined: Option<String> = conn
+        .query_row(
+            "select team from team_members where guild = ?1 and user = ?2",
+            params![guild, user],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if joined.is_some() {
+        return Ok(joined);
+    }
+    let mut stmt = conn.prepare(
+        "select name, role from teams where guild = ?1 and role is not null order by name",
+    )?;
+    let teams = stmt
+        .query_map(params![guild], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()?;
+    for (name, role) in teams {
+        if roles.iter().any(|r| r.to_string() == role) {
+            return Ok(Some(name));
+        }
+    }
+    Ok(None)
+}
+
+// Credits a correct trivia answer to the team of the user, if they have one
+pub fn update_team_points(guild: &str, user: &str, roles: &[RoleId]) -> Result<()> {
+    let conn = Connection::open("points.db")?;
+    let team = match find_team(&conn, guild, user, roles)? {
+        Some(body) => body,
+        None => return Ok(()),
+    };
+    let season = current_season(&conn, guild)?;
+    conn.execute(
+        "insert into team_points (guild, team, season, score) values (?1, ?2, ?3, 1)
+        on conflict(guild, team, season) do update set score = score + 1",
+        params![guild, team, season],
+    )?;
+    Ok(())
+}
+
+// Leaderboard lines for the teams of a guild in the given season
+pub fn team_leaderboard(conn: &Connection, guild: &str, season: i64) -> Result<String> {
+    let mut stmt = conn.prepare(
+        "select teams.name, coalesce(team_points.score, 0) as score from teams
+        left join team_points on team_points.guild = teams.guild
+            and team_points.team = teams.name and team_points.season = ?2
+        where teams.guild = ?1 order by score desc, teams.name",
+    )?;
+    let scores = stmt
+        .query_map(params![guild, season], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?
+        .collect::<Result<Vec<(String, i64)>, rusqlite::Error>>()?;
+    let mut msg_content = String::new();
+    for (name, score) in scores {
+        msg_content.push_str(&format!("\n{}: {}", name, score));
+    }
+    Ok(msg_content)
+}
+
+// Handles /team create|join|leave|leaderboard|reset
+pub fn team_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let guild = match cmd.guild_id {
+        Some(body) => body.to_string(),
+        None => return Ok(message("Command was not run in guild")),
+    };
+    let user = cmd.user.id.to_string();
+    let (subcmd, sub_options) = subcommand(cmd, "team")?;
+    let mut name: Option<String> = None;
+    let mut role: Option<String> = None;
+    let mut season: Option<i64> = None;
+    for option in sub_options {
+        match (option.name, &option.value) {
+            ("name", ResolvedValue::String(s)) => name = Some(s.trim().to_string()),
+            ("role", ResolvedValue::Role(r)) => role = Some(r.id.to_string()),
+            ("season", ResolvedValue::Integer(n)) => season = Some(*n),
+            _ => {}
+        }
+    }
+    let conn = Connection::open("points.db")?;
+
+    let response = match subcmd {
+        "create" => {
+            let name = name.unwrap_or_default();
+            if name.is_empty() {
+                return Ok(message("The team needs a name"));
+            }
+            // Linking a role hands its members to the team, so it takes the right to manage roles
+            if role.is_some() && !has_permission(cmd.member.as_deref(), Permissions::MANAGE_ROLES) {
+                return Ok(message(
+                    "Only members with Manage Roles can link a role to a team",
+                ));
+            }
+            let inserted = conn.execute(
+                "insert or ignore into teams (guild, name, role) values (?1, ?2, ?3)",
+                params![guild, name, role],
+            )?;
+            if inserted == 0 {
+                message(format!("Team {} already exists", name))
+            } else if let Some(role) = role {
+                message(format!(
+                    "Created team {}, members of <@&{}> play for it",
+                    name, role
+                ))
+            } else {
+                message(format!(
+                    "Created team {}, use `/team join` to play for it",
+                    name
+                ))
+            }
+        }
+        "join" => {
+            let name = name.unwrap_or_default();
+            let exists: Option<String> = conn
+                .query_row(
+                    "select name from teams where guild = ?1 and name = ?2",
+                    params![guild, name],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match exists {
+                Some(team) => {
+                    conn.execute(
+                        "insert into team_members (guild, user, team) values (?1, ?2, ?3)
+                        on conflict(guild, user) do update set team = excluded.team",
+                        params![guild, user, team],
+                    )?;
+                    message(format!("{} joined team {}", cmd.user.name, team))
+                }
+                None => message(format!("There is no team called {}", name)),
+            }
+        }
+        "leave" => {
+            let left = conn.execute(
+                "delete from team_members where guild = ?1 and user = ?2",
+                params![guild, user],
+            )?;
+            if left == 0 {
+                message("You haven't joined a team")
+            } else {
+                message(format!("{} left their team", cmd.user.name))
+            }
+        }
+        "leaderboard" => {
+            let season = match season {
+                Some(body) => body,
+                None => current_season(&conn, &guild)?,
+            };
+            let board = team_leaderboard(&conn, &guild, season)?;
+            if board.is_empty() {
+                message("No teams on this guild yet, use `/team create`")
+            } else {
+                message(format!("## Team leaderboard (season {}){}", season, board))
+            }
+        }
+        "reset" => {
+            if !has_permission(cmd.member.as_deref(), Permissions::MANAGE_GUILD) {
+                return Ok(message(
+                    "Only members with Manage Server can reset team scores",
+                ));
+            }
+            let next = current_season(&conn, &guild)? + 1;
+            conn.execute(
+                "insert into team_seasons (guild, season) values (?1, ?2)
+                on conflict(guild) do update set season = excluded.season",
+                params![guild, next],
+            )?;
+            message(format!(
+                "Team scores were reset, season {} has started",
+                next
+            ))
+        }
+        _ => message("Unknown team command"),
+    };
+    Ok(response)
+}
@@ -0,0 +1,362 @@
+use crate::commands::interaction::{message, subcommand};
+use crate::commands::points::{send_trivia, Category};
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::all::{CommandInteraction, GuildChannel, ResolvedValue};
+use serenity::builder::{CreateEmbed, CreateEmbedFooter, CreateMessage};
+use serenity::prelude::*;
+use std::time::Duration;
+use tokio::time::sleep;
+
+pub const DEFAULT_ROUNDS: i64 = 20;
+
+// Lifecycle of a tournament, stored as text in the tournaments table
+const REGISTRATION: &str = "registration";
+const RUNNING: &str = "running";
+const SUDDEN_DEATH: &str = "sudden_death";
+
+pub struct Tournament {
+    pub channel: String,
+    pub host: String,
+    pub rounds: i64,
+    pub round: i64,
+    pub state: String,
+    pub category: Category,
+    // Message ID of the question being played, only replies to it count
+    pub question: Option<String>,
+}
+
+struct Player {
+    name: String,
+    score: i64,
+}
+
+impl Tournament {
+    // Records the user's answer to the current question. False when the user isn't a
+    // contender or already answered it, wrong answers included.
+    pub fn attempt(&self, user: &str) -> Result<bool> {
+        let conn = Connection::open("points.db")?;
+        let recorded = conn.execute(
+            "update tournament_players set attempted = ?1
+            where channel = ?2 and player = ?3 and contender = 1
+            and (attempted is null or attempted != ?1)",
+            params![self.question, self.channel, user],
+        )?;
+        Ok(recorded > 0)
+    }
+
+    // Whether answers in the channel count towards the tournament instead of the leaderboard
+    pub fn is_playing(&self) -> bool {
+        self.state == RUNNING || self.state == SUDDEN_DEATH
+    }
+
+    // Whether the message is the question of the current round
+    pub fn is_current(&self, message: &str) -> bool {
+        self.question.as_deref() == Some(message)
+    }
+}
+
+pub fn get_tournament(channel: &str) -> Result<Option<Tournament>> {
+    let conn = Connection::open("points.db")?;
+    let tournament = conn
+        .query_row(
+            "select channel, host, rounds, round, state, category, question from tournaments where channel = ?1",
+            params![channel],
+            |row| {
+                let category: String = row.get(5)?;
+                Ok(Tournament {
+                    channel: row.get(0)?,
+                    host: row.get(1)?,
+                    rounds: row.get(2)?,
+                    round: row.get(3)?,
+                    state: row.get(4)?,
+                    category: Category::parse(&category),
+                    question: row.get(6)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(tournament)
+}
+
+fn get_players(conn: &Connection, channel: &str) -> Result<Vec<Player>> {
+    let mut stmt = conn.prepare(
+        "select name, score from tournament_players where channel = ?1 order by score desc, name",
+    )?;
+    let players = stmt
+        .query_map(params![channel], |row| {
+            Ok(Player {
+                name: row.get(0)?,
+                score: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<Player>, rusqlite::Error>>()?;
+    Ok(players)
+}
+
+fn delete_tournament(conn: &Connection, channel: &str) -> Result<()> {
+    conn.execute(
+        "delete from tournament_players where channel = ?1",
+        params![channel],
+    )?;
+    conn.execute(
+        "delete from tournaments where channel = ?1",
+        params![channel],
+    )?;
+    Ok(())
+}
+
+// Takes the current question out of play, false when it was already answered or skipped
+fn claim_question(conn: &Connection, tournament: &Tournament) -> Result<bool> {
+    let claimed = conn.execute(
+        "update tournaments set question = null
+        where channel = ?1 and round = ?2 and state = ?3 and question = ?4",
+        params![
+            tournament.channel,
+            tournament.round,
+            tournament.state,
+            tournament.question
+        ],
+    )?;
+    Ok(claimed > 0)
+}
+
+// Handles /tournament start|join|begin|skip|cancel
+pub async fn tournament_cmd(cmd: &CommandInteraction, ctx: &Context) -> Result<()> {
+    let guild = match cmd.guild_id {
+        Some(body) => body.to_string(),
+        None => {
+            cmd.create_response(&ctx, message("Tournaments can only be run in a guild"))
+                .await?;
+            return Ok(());
+        }
+    };
+    let channel = cmd.channel_id.to_string();
+    let user = cmd.user.id.to_string();
+    let (subcmd, sub_options) = subcommand(cmd, "tournament")?;
+    let conn = Connection::open("points.db")?;
+    let tournament = get_tournament(&channel)?;
+    let mut skipped: Option<Tournament> = None;
+
+    let response = match (subcmd, tournament) {
+        ("start", Some(_)) => message("A tournament is already running in this channel"),
+        ("start", None) => {
+            let mut rounds = DEFAULT_ROUNDS;
+            let mut category = Category::General;
+            for option in sub_options {
+                match (option.name, &option.value) {
+                    ("rounds", ResolvedValue::Integer(n)) => rounds = *n,
+                    ("category", ResolvedValue::String(s)) => category = Category::parse(s),
+                    _ => {}
+                }
+            }
+            conn.execute(
+                "insert into tournaments (channel, guild, host, rounds, round, state, category) values (?1, ?2, ?3, ?4, 0, ?5, ?6)",
+                params![channel, guild, user, rounds, REGISTRATION, category.as_str()],
+            )?;
+            conn.execute(
+                "insert into tournament_players (channel, player, name, score, contender) values (?1, ?2, ?3, 0, 1)",
+                params![channel, user, cmd.user.name],
+            )?;
+            message(format!(
+                "## Trivia tournament\n<@{}> opened a {} question tournament ({} questions)! Use `/tournament join` to register, then the host starts it with `/tournament begin`.",
+                user, rounds, category.as_str()
+            ))
+        }
+        (_, None) => message("There is no tournament in this channel, use `/tournament start`"),
+        ("join", Some(tournament)) => {
+            if tournament.state != REGISTRATION {
+                message("Registration for this tournament is closed")
+            } else {
+                let inserted = conn.execute(
+                    "insert or ignore into tournament_players (channel, player, name, score, contender) values (?1, ?2, ?3, 0, 1)",
+                    params![channel, user, cmd.user.name],
+                )?;
+                if inserted == 0 {
+                    message("You are already registered")
+                } else {
+                    message(format!("{} joined the tournament!", cmd.user.name))
+                }
+            }
+        }
+        ("begin", Some(tournament)) => {
+            if tournament.host != user {
+                message("Only the host can begin the tournament")
+            } else if tournament.state != REGISTRATION {
+                message("The tournament has already begun")
+            } else {
+                conn.execute(
+                    "update tournaments set state = ?1, round = 1 where channel = ?2",
+                    params![RUNNING, channel],
+                )?;
+                let players = get_players(&conn, &channel)?;
+                let names: Vec<String> = players.into_iter().map(|p| p.name).collect();
+                message(format!(
+                    "## The tournament begins!\n{} rounds, players: {}\nReply to each question with the right answer, only registered players score.",
+                    tournament.rounds,
+                    names.join(", ")
+                ))
+            }
+        }
+        ("skip", Some(tournament)) => {
+            if tournament.host != user {
+                message("Only the host can skip a question")
+            } else if !tournament.is_playing() || !claim_question(&conn, &tournament)? {
+                message("There is no question to skip")
+            } else {
+                skipped = Some(tournament);
+                message("The question was skipped, nobody scores it")
+            }
+        }
+        ("cancel", Some(tournament)) => {
+            if tournament.host != user {
+                message("Only the host can cancel the tournament")
+            } else {
+                delete_tournament(&conn, &channel)?;
+                message("The tournament was cancelled")
+            }
+        }
+        _ => message("Unknown tournament command"),
+    };
+    cmd.create_response(&ctx, response).await?;
+
+    // The match goes on after a skipped question
+    if let Some(tournament) = skipped {
+        if let Some(guild_channel) = cmd.channel_id.to_channel(&ctx).await?.guild() {
+            advance(tournament, &guild_channel, ctx).await?;
+        }
+    }
+
+    // The first question follows the announcement
+    if subcmd == "begin" {
+        if let Some(tournament) = get_tournament(&channel)? {
+            if tournament.state == RUNNING && tournament.round == 1 {
+                if let Some(guild_channel) = cmd.channel_id.to_channel(&ctx).await?.guild() {
+                    send_round(&guild_channel, &tournament, ctx).await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn send_round(channel: &GuildChannel, tournament: &Tournament, ctx: &Context) -> Result<()> {
+    let header = if tournament.state == SUDDEN_DEATH {
+        String::from("### Sudden death question")
+    } else {
+        format!("### Round {}/{}", tournament.round, tournament.rounds)
+    };
+    channel.say(&ctx.http, header).await?;
+    let question = send_trivia(channel, tournament.category, ctx).await?;
+    let conn = Connection::open("points.db")?;
+    conn.execute(
+        "update tournaments set question = ?1 where channel = ?2 and round = ?3",
+        params![
+            question.id.to_string(),
+            tournament.channel,
+            tournament.round
+        ],
+    )?;
+    Ok(())
+}
+
+// Credits a correct tournament answer and moves the match forward. Only the first correct
+// reply to the current question counts, later ones find it already answered.
+pub async fn record_answer(
+    tournament: Tournament,
+    user: &str,
+    channel: &GuildChannel,
+    ctx: &Context,
+) -> Result<()> {
+    let conn = Connection::open("points.db")?;
+    if !claim_question(&conn, &tournament)? {
+        return Ok(());
+    }
+    conn.execute(
+        "update tournament_players set score = score + 1 where channel = ?1 and player = ?2",
+        params![tournament.channel, user],
+    )?;
+    advance(tournament, channel, ctx).await
+}
+
+// Moves the match past a question that was answered or skipped
+async fn advance(tournament: Tournament, channel: &GuildChannel, ctx: &Context) -> Result<()> {
+    let conn = Connection::open("points.db")?;
+    if tournament.state == RUNNING && tournament.round < tournament.rounds {
+        conn.execute(
+            "update tournaments set round = round + 1 where channel = ?1 and round = ?2",
+            params![tournament.channel, tournament.round],
+        )?;
+        sleep(Duration::from_secs(3)).await;
+        if let Some(next) = get_tournament(&tournament.channel)? {
+            send_round(channel, &next, ctx).await?;
+        }
+        return Ok(());
+    }
+
+    // Last round played or sudden death over, check for a tie at the top
+    let players = get_players(&conn, &tournament.channel)?;
+    let best = players.first().map(|p| p.score).unwrap_or(0);
+    let leaders = players.iter().filter(|p| p.score == best).count();
+    if tournament.state == SUDDEN_DEATH && leaders > 1 {
+        // A skipped sudden death question is replaced
+        sleep(Duration::from_secs(3)).await;
+        if let Some(next) = get_tournament(&tournament.channel)? {
+            send_round(channel, &next, ctx).await?;
+        }
+        return Ok(());
+    }
+    if tournament.state == RUNNING && leaders > 1 {
+        conn.execute(
+            "update tournament_players set contender = (score = ?1) where channel = ?2",
+            params![best, tournament.channel],
+        )?;
+        conn.execute(
+            "update tournaments set state = ?1 where channel = ?2",
+            params![SUDDEN_DEATH, tournament.channel],
+        )?;
+        let tied: Vec<&str> = players
+            .iter()
+            .filter(|p| p.score == best)
+            .map(|p| p.name.as_str())
+            .collect();
+        channel
+            .say(
+                &ctx.http,
+                format!(
+                    "It's a tie between {}! The first to answer the next question wins.",
+                    tied.join(", ")
+                ),
+            )
+            .await?;
+        sleep(Duration::from_secs(3)).await;
+        if let Some(next) = get_tournament(&tournament.channel)? {
+            send_round(channel, &next, ctx).await?;
+        }
+        return Ok(());
+    }
+
+    delete_tournament(&conn, &tournament.channel)?;
+    let mut standings = String::new();
+    for (i, player) in players.iter().enumerate() {
+        standings.push_str(&format!(
+            "**{}.** {}: {}\n",
+            i + 1,
+            player.name,
+            player.score
+        ));
+    }
+    let winner = players.first().map(|p| p.name.clone()).unwrap_or_default();
+    let embed = CreateEmbed::new()
+        .title("🏆 Tournament results")
+        .description(standings)
+        .field("Winner", winner, false)
+        .footer(CreateEmbedFooter::new(format!(
+            "{} rounds played",
+            tournament.rounds
+        )));
+    channel
+        .send_message(&ctx.http, CreateMessage::new().embed(embed))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,429 @@
+use crate::commands::browse::episode_line;
+use crate::commands::episode::{
+    episode_embed, get_episode, row_to_episode, search_episodes, Episode, EPISODE_COLUMNS,
+};
+use crate::commands::interaction::{ephemeral, message, subcommand};
+use crate::commands::metadata::{metadata_providers, title_metadata};
+use crate::http::HttpClient;
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::all::{
+    ButtonStyle, CommandInteraction, ComponentInteraction, ResolvedOption, ResolvedValue,
+};
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateInteractionResponse, EditInteractionResponse,
+};
+
+// Most watchlist entries listed in a single message
+const MAX_LISTED: usize = 20;
+
+// Filters of a recommendation, kept in the reroll button so a reroll uses the same ones
+#[derive(Default)]
+struct WatchFilters {
+    season: Option<i64>,
+    doctor: Option<i64>,
+    min_rating: Option<f64>,
+    fresh: bool,
+}
+
+impl WatchFilters {
+    fn encode(&self) -> String {
+        fn part<T: ToString>(value: &Option<T>) -> String {
+            match value {
+                Some(v) => v.to_string(),
+                None => String::from("-"),
+            }
+        }
+        format!(
+            "{}:{}:{}:{}",
+            part(&self.season),
+            part(&self.doctor),
+            part(&self.min_rating),
+            self.fresh
+        )
+    }
+
+    fn decode(parts: &[&str]) -> Result<WatchFilters> {
+        fn part<T: std::str::FromStr>(value: &str) -> Option<T> {
+            match value {
+                "-" => None,
+                v => v.parse().ok(),
+            }
+        }
+        match parts {
+            [season, doctor, rating, fresh] => Ok(WatchFilters {
+                season: part(season),
+                doctor: part(doctor),
+                min_rating: part(rating),
+                fresh: *fresh == "true",
+            }),
+            _ => Err(anyhow::anyhow!("Failed to parse watch filters")),
+        }
+    }
+}
+
+// Picks a random catalog episode matching the filters and remembers it was recommended
+fn recommend(conn: &Connection, user: &str, filters: &WatchFilters) -> Result<Option<Episode>> {
+    let id: Option<String> = conn
+        .query_row(
+            "select e.id from episodes e left join title_metadata m on m.id = e.id
+            where (?1 is null or (e.era = 'revival' and e.season = ?1))
+            and (?2 is null or e.doctor = ?2)
+            and (?3 is null or m.rating >= ?3)
+            and (?4 = 0 or e.id not in (select episode_id from recommendations where user = ?5))
+            order by random() limit 1",
+            params![
+                filters.season,
+                filters.doctor,
+                filters.min_rating,
+                filters.fresh,
+                user
+            ],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let id = match id {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    conn.execute(
+        "insert into recommendations (user, episode_id, recommended_at) values (?1, ?2, datetime('now'))
+        on conflict(user, episode_id) do update set recommended_at = excluded.recommended_at",
+        params![user, id],
+    )?;
+    get_episode(conn, &id)
+}
+
+async fn recommendation_message(
+    user: &str,
+    filters: &WatchFilters,
+    http: &HttpClient,
+) -> Result<EditInteractionResponse> {
+    let providers = metadata_providers(http);
+    let episode = match recommend(&Connection::open("episodes.db")?, user, filters)? {
+        Some(body) => body,
+        None => {
+            return Ok(EditInteractionResponse::new()
+                .content("No episodes match these filters, try loosening them")
+                .embeds(Vec::new())
+                .components(Vec::new()))
+        }
+    };
+    let metadata = title_metadata(&providers, &episode).await?;
+    let buttons = vec![
+        CreateButton::new(format!("watch:reroll:{}:{}", user, filters.encode()))
+            .label("🎲 Reroll")
+            .style(ButtonStyle::Secondary),
+        CreateButton::new(format!("watch:seen:{}", episode.id))
+            .label("✅ Mark watched")
+            .style(ButtonStyle::Success),
+    ];
+    Ok(EditInteractionResponse::new()
+        .content("## Tonight you could watch")
+        .embeds(vec![episode_embed(&episode, metadata.as_ref())])
+        .components(vec![CreateActionRow::Buttons(buttons)]))
+}
+
+// Handles /watch [season] [doctor] [rating] [new]
+pub async fn watch_cmd_response(
+    cmd: &CommandInteraction,
+    http: &HttpClient,
+) -> Result<EditInteractionResponse> {
+    let mut filters = WatchFilters::default();
+    for option in cmd.data.options() {
+        match (option.name, option.value) {
+            ("season", ResolvedValue::Integer(n)) => filters.season = Some(n),
+            ("doctor", ResolvedValue::Integer(n)) => filters.doctor = Some(n),
+            ("rating", ResolvedValue::Number(n)) => filters.min_rating = Some(n),
+            ("new", ResolvedValue::Boolean(b)) => filters.fresh = b,
+            _ => {}
+        }
+    }
+    recommendation_message(&cmd.user.id.to_string(), &filters, http).await
+}
+
+// Refusal for members rerolling someone else's recommendation, the button carries the ID
+// of the member it was made for
+pub fn watch_reroll_refusal(component: &ComponentInteraction) -> Option<CreateInteractionResponse> {
+    let owner = component.data.custom_id.split(':').nth(2)?;
+    if owner == component.user.id.to_string() {
+        return None;
+    }
+    Some(ephemeral(
+        "Only the member who asked for this recommendation can reroll it, use `/watch` for your own",
+    ))
+}
+
+// Handles the reroll button, replacing the recommendation in place
+pub async fn watch_reroll_response(
+    component: &ComponentInteraction,
+    http: &HttpClient,
+) -> Result<EditInteractionResponse> {
+    let parts: Vec<&str> = component.data.custom_id.split(':').collect();
+    let filters = match parts[..] {
+        [_, "reroll", _, ..] => WatchFilters::decode(&parts[3..])?,
+        _ => return Err(anyhow::anyhow!("Failed to parse watch button")),
+    };
+    recommendation_message(&component.user.id.to_string(), &filters, http).await
+}
+
+// Handles the mark watched button
+pub fn watch_seen_response(component: &ComponentInteraction) -> Result<CreateInteractionResponse> {
+    let user = component.user.id.to_string();
+    let parts: Vec<&str> = component.data.custom_id.split(':').collect();
+    let id = match parts[..] {
+        [_, "seen", id] => id,
+        _ => return Err(anyhow::anyhow!("Failed to parse watch button")),
+    };
+    let conn = Connection::open("episodes.db")?;
+    let title = match get_episode(&conn, id)? {
+        Some(episode) => episode.full_title(),
+        None => return Err(anyhow::anyhow!("Unknown episode {}", id)),
+    };
+    mark_watched(&conn, &user, id)?;
+    Ok(ephemeral(format!("Marked {} as watched", title)))
+}
+
+// Watching an episode also takes it off the watchlist
+pub fn mark_watched(conn: &Connection, user: &str, id: &str) -> Result<()> {
+    conn.execute(
+        "insert or ignore into watch_history (user, episode_id, watched_at) values (?1, ?2, datetime('now'))",
+        params![user, id],
+    )?;
+    conn.execute(
+        "delete from watchlist where user = ?1 and episode_id = ?2",
+        params![user, id],
+    )?;
+    Ok(())
+}
+
+// Episodes named by an option, every part of a serial when the title matches exactly,
+// otherwise the best search result
+fn resolve_episodes(conn: &Connection, name: &str) -> Result<Vec<Episode>> {
+    let mut stmt = conn.prepare(&format!(
+        "select {} from episodes where title = ?1 collate nocase order by era, season, episode, part",
+        EPISODE_COLUMNS
+    ))?;
+    let episodes = stmt
+        .query_map(params![name.trim()], row_to_episode)?
+        .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+    if !episodes.is_empty() {
+        return Ok(episodes);
+    }
+    search_episodes(conn, name, 1)
+}
+
+fn name_option(options: &[ResolvedOption]) -> String {
+    options
+        .iter()
+        .find(|o| o.name == "name")
+        .and_then(|o| match o.value {
+            ResolvedValue::String(s) => Some(s.to_string()),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+fn describe(episodes: &[Episode]) -> String {
+    match episodes {
+        [episode] => episode.full_title(),
+        _ => format!("{} ({} parts)", episodes[0].title, episodes.len()),
+    }
+}
+
+// Handles /watchlist add|remove|list
+pub fn watchlist_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let user = cmd.user.id.to_string();
+    let (subcmd, sub_options) = subcommand(cmd, "watchlist")?;
+    let conn = Connection::open("episodes.db")?;
+    if subcmd == "list" {
+        let mut stmt = conn.prepare(&format!(
+            "select {} from episodes where id in (select episode_id from watchlist where user = ?1)
+            order by era desc, season, episode, part",
+            EPISODE_COLUMNS
+        ))?;
+        let episodes = stmt
+            .query_map(params![user], row_to_episode)?
+            .collect::<Result<Vec<Episode>, rusqlite::Error>>()?;
+        if episodes.is_empty() {
+            return Ok(message(
+                "Your watchlist is empty, use `/watchlist add` to fill it",
+            ));
+        }
+        let mut msg_content = format!("## {}'s watchlist\n", cmd.user.name);
+        for episode in episodes.iter().take(MAX_LISTED) {
+            msg_content.push_str(&episode_line(episode));
+        }
+        if episodes.len() > MAX_LISTED {
+            msg_content.push_str(&format!("...and {} more", episodes.len() - MAX_LISTED));
+        }
+        return Ok(message(msg_content));
+    }
+    let name = name_option(&sub_options);
+    let episodes = resolve_episodes(&conn, &name)?;
+    if episodes.is_empty() {
+        return Ok(message(format!("No episode found for {}", name)));
+    }
+    let response = match subcmd {
+        "add" => {
+            for episode in &episodes {
+                conn.execute(
+                    "insert or ignore into watchlist (user, episode_id, added_at) values (?1, ?2, datetime('now'))",
+                    params![user, episode.id],
+                )?;
+            }
+            message(format!("Added {} to your watchlist", describe(&episodes)))
+        }
+        "remove" => {
+            let mut removed = 0;
+            for episode in &episodes {
+                removed += conn.execute(
+                    "delete from watchlist where user = ?1 and episode_id = ?2",
+                    params![user, episode.id],
+                )?;
+            }
+            if removed == 0 {
+                message(format!("{} isn't on your watchlist", describe(&episodes)))
+            } else {
+                message(format!(
+                    "Removed {} from your watchlist",
+                    describe(&episodes)
+                ))
+            }
+        }
+        _ => return Err(anyhow::anyhow!("Unknown watchlist subcommand {}", subcmd)),
+    };
+    Ok(response)
+}
+
+// Watched and total episodes of every season, keyed by era and season number
+fn season_progress(conn: &Connection, user: &str) -> Result<Vec<(String, i64, i64, i64)>> {
+    let mut stmt = conn.prepare(
+        "select e.era, e.season, count(*), count(w.episode_id) from episodes e
+        left join watch_history w on w.episode_id = e.id and w.user = ?1
+        group by e.era, e.season order by e.era desc, e.season",
+    )?;
+    let progress = stmt
+        .query_map(params![user], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<Result<Vec<(String, i64, i64, i64)>, rusqlite::Error>>()?;
+    Ok(progress)
+}
+
+fn season_label(era: &str, season: i64) -> String {
+    if era == "classic" {
+        format!("Classic season {}", season)
+    } else {
+        format!("Season {}", season)
+    }
+}
+
+// Progress lines of a user, with a friend's progress next to each season when comparing
+fn progress_message(
+    conn: &Connection,
+    user: &str,
+    name: &str,
+    friend: Option<(&str, &str)>,
+) -> Result<String> {
+    let mine = season_progress(conn, user)?;
+    let theirs = match friend {
+        Some((friend, _)) => Some(season_progress(conn, friend)?),
+        None => None,
+    };
+    let mut msg_content = match friend {
+        Some((_, friend_name)) => format!("## {} vs {}\n", name, friend_name),
+        None => format!("## {}'s progress\n", name),
+    };
+    let (mut watched, mut total, mut friend_watched) = (0, 0, 0);
+    for (i, (era, season, count, seen)) in mine.iter().enumerate() {
+        let friend_seen = theirs.as_ref().map(|t| t[i].3).unwrap_or(0);
+        total += count;
+        watched += seen;
+        friend_watched += friend_seen;
+        // Seasons nobody has started would only be noise
+        if *seen == 0 && friend_seen == 0 {
+            continue;
+        }
+        let line = match friend {
+            Some((_, friend_name)) => format!(
+                "{}: {}/{} watched ({}: {}/{})\n",
+                season_label(era, *season),
+                seen,
+                count,
+                friend_name,
+                friend_seen,
+                count
+            ),
+            None => format!(
+                "{}: {}/{} watched\n",
+                season_label(era, *season),
+                seen,
+                count
+            ),
+        };
+        msg_content.push_str(&line);
+    }
+    if watched == 0 && friend_watched == 0 {
+        msg_content.push_str("No episodes marked as watched yet, use `/watched add`\n");
+    }
+    match friend {
+        Some((_, friend_name)) => msg_content.push_str(&format!(
+            "**Total: {}/{} watched ({}: {}/{})**",
+            watched, total, friend_name, friend_watched, total
+        )),
+        None => msg_content.push_str(&format!("**Total: {}/{} watched**", watched, total)),
+    }
+    Ok(msg_content)
+}
+
+// Handles /watched add|remove|progress
+pub fn watched_cmd_response(cmd: &CommandInteraction) -> Result<CreateInteractionResponse> {
+    let user = cmd.user.id.to_string();
+    let (subcmd, sub_options) = subcommand(cmd, "watched")?;
+    let conn = Connection::open("episodes.db")?;
+    if subcmd == "progress" {
+        let friend: Option<(String, String)> = sub_options.iter().find_map(|o| match &o.value {
+            ResolvedValue::User(u, _) if o.name == "member" => {
+                Some((u.id.to_string(), u.name.clone()))
+            }
+            _ => None,
+        });
+        if friend.is_some() && cmd.guild_id.is_none() {
+            return Ok(message("Comparing progress only works in a guild"));
+        }
+        let friend = friend
+            .as_ref()
+            .map(|(id, name)| (id.as_str(), name.as_str()));
+        return Ok(message(progress_message(
+            &conn,
+            &user,
+            &cmd.user.name,
+            friend,
+        )?));
+    }
+    let name = name_option(&sub_options);
+    let episodes = resolve_episodes(&conn, &name)?;
+    if episodes.is_empty() {
+        return Ok(message(format!("No episode found for {}", name)));
+    }
+    let response = match subcmd {
+        "add" => {
+            for episode in &episodes {
+                mark_watched(&conn, &user, &episode.id)?;
+            }
+            message(format!("Marked {} as watched", describe(&episodes)))
+        }
+        "remove" => {
+            for episode in &episodes {
+                conn.execute(
+                    "delete from watch_history where user = ?1 and episode_id = ?2",
+                    params![user, episode.id],
+                )?;
+            }
+            message(format!("Marked {} as unwatched", describe(&episodes)))
+        }
+        _ => return Err(anyhow::anyhow!("Unknown watched subcommand {}", subcmd)),
+    };
+    Ok(response)
+}
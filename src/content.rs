@@ -0,0 +1,274 @@
+use crate::commands::doctor::find_doctor;
+use crate::commands::interaction::{ephemeral, has_permission};
+use crate::commands::points::Question;
+use crate::commands::quote::{sync_quotes, Quote};
+use anyhow::Result;
+use rusqlite::Connection;
+use serenity::all::{CommandInteraction, Permissions};
+use serenity::builder::CreateInteractionResponse;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::time::sleep;
+
+pub const QUESTIONS_FILE: &str = "questions.json";
+pub const QUOTES_FILE: &str = "quotes.json";
+// How often the watcher looks for edited content files
+const WATCH_SECS: u64 = 5;
+
+// Trivia questions as last loaded from their file. Quotes are served from the database,
+// which reloads sync them into.
+#[derive(Default, Clone)]
+pub struct Content {
+    pub questions: Vec<Question>,
+}
+
+// Handlers read a snapshot of the content, reloads swap in a new one
+static CONTENT: RwLock<Option<Arc<Content>>> = RwLock::new(None);
+// The watcher and /reload never load files at the same time
+static RELOADING: Mutex<()> = Mutex::new(());
+
+pub fn loaded_content() -> Arc<Content> {
+    let content = CONTENT.read().unwrap_or_else(|e| e.into_inner());
+    content.clone().unwrap_or_default()
+}
+
+pub fn read_questions() -> Result<Vec<Question>> {
+    parse_questions(&fs::read_to_string(QUESTIONS_FILE)?)
+}
+
+// Parses questions.json. Every question needs the **Q marker replies are matched by and
+// an answer that is one of its choices.
+fn parse_questions(body: &str) -> Result<Vec<Question>> {
+    let questions: Vec<Question> = serde_json::from_str(body)?;
+    if questions.is_empty() {
+        return Err(anyhow::anyhow!("there are no questions"));
+    }
+    let mut seen: HashSet<&str> = HashSet::new();
+    for (i, question) in questions.iter().enumerate() {
+        let choice = format!("{}.", question.answer);
+        if !question.question.contains("**Q") {
+            return Err(anyhow::anyhow!("question {} has no **Q marker", i + 1));
+        }
+        if !["a", "b", "c", "d"].contains(&question.answer.as_str())
+            || !question
+                .question
+                .lines()
+                .any(|line| line.trim_start().starts_with(&choice))
+        {
+            return Err(anyhow::anyhow!(
+                "question {} has no choice {}",
+                i + 1,
+                question.answer
+            ));
+        }
+        if !seen.insert(&question.question) {
+            return Err(anyhow::anyhow!("question {} is a duplicate", i + 1));
+        }
+    }
+    Ok(questions)
+}
+
+// Reads quotes.json with the hash of the file
+pub fn read_quotes() -> Result<(Vec<Quote>, String)> {
+    let body = fs::read_to_string(QUOTES_FILE)?;
+    let hash = format!("{:x}", Sha256::digest(body.as_bytes()));
+    Ok((parse_quotes(&body)?, hash))
+}
+
+// Parses quotes.json. Every quote needs its text and speaker, and a given Doctor has to
+// be one we know.
+fn parse_quotes(body: &str) -> Result<Vec<Quote>> {
+    let quotes: Vec<Quote> = serde_json::from_str(body)?;
+    let mut seen: HashSet<&str> = HashSet::new();
+    for (i, quote) in quotes.iter().enumerate() {
+        if quote.quote.trim().is_empty() || quote.author.trim().is_empty() {
+            return Err(anyhow::anyhow!(
+                "quote {} needs a quote and an author",
+                i + 1
+            ));
+        }
+        if let Some(doctor) = &quote.doctor {
+            if find_doctor(doctor).is_none() {
+                return Err(anyhow::anyhow!(
+                    "quote {} names an unknown Doctor {}",
+                    i + 1,
+                    doctor
+                ));
+            }
+        }
+        if !seen.insert(quote.quote.trim()) {
+            return Err(anyhow::anyhow!("quote {} is a duplicate", i + 1));
+        }
+    }
+    Ok(quotes)
+}
+
+// Loads the content files into the store, quotes are also synced into the database. A
+// file that can't be read or doesn't validate is reported and its previous data is kept.
+pub fn reload_content() -> Vec<String> {
+    let _reloading = RELOADING.lock().unwrap_or_else(|e| e.into_inner());
+    let mut next = (*loaded_content()).clone();
+    let mut report: Vec<String> = Vec::new();
+    match read_questions() {
+        Ok(questions) => {
+            report.push(format!(
+                "✅{} loaded ({} questions)",
+                QUESTIONS_FILE,
+                questions.len()
+            ));
+            next.questions = questions;
+        }
+        Err(e) => report.push(format!(
+            "❌{} not loaded, keeping {} questions: {}",
+            QUESTIONS_FILE,
+            next.questions.len(),
+            e
+        )),
+    }
+    let quotes = read_quotes().and_then(|(quotes, hash)| {
+        let mut conn = Connection::open("episodes.db")?;
+        sync_quotes(&mut conn, &quotes, &hash)?;
+        Ok(quotes.len())
+    });
+    match quotes {
+        Ok(count) => report.push(format!("✅{} loaded ({} quotes)", QUOTES_FILE, count)),
+        Err(e) => report.push(format!(
+            "❌{} not loaded, keeping the quotes already stored: {}",
+            QUOTES_FILE, e
+        )),
+    }
+    *CONTENT.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(next));
+    report
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Reloads the content files whenever one of them changes on disk
+pub async fn watch_content() {
+    let files = [QUESTIONS_FILE, QUOTES_FILE];
+    let mut seen: Vec<Option<SystemTime>> = files.iter().map(|f| modified(f)).collect();
+    loop {
+        sleep(Duration::from_secs(WATCH_SECS)).await;
+        let current: Vec<Option<SystemTime>> = files.iter().map(|f| modified(f)).collect();
+        if current != seen {
+            seen = current;
+            match tokio::task::spawn_blocking(reload_content).await {
+                Ok(report) => {
+                    for line in report {
+                        println!("{}", line);
+                    }
+                }
+                Err(e) => println!("Failed to reload the content files: {}", e),
+            }
+        }
+    }
+}
+
+// Handles /reload, for administrators. Files are read and synced off the async runtime.
+pub async fn reload_cmd_response(cmd: &CommandInteraction) -> CreateInteractionResponse {
+    let content = if has_permission(cmd.member.as_deref(), Permissions::ADMINISTRATOR) {
+        match tokio::task::spawn_blocking(reload_content).await {
+            Ok(report) => report.join("\n"),
+            Err(e) => format!("Failed to reload the content files: {}", e),
+        }
+    } else {
+        String::from("Only administrators can reload the content files")
+    };
+    ephemeral(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(result: Result<impl Sized>) -> String {
+        match result {
+            Ok(_) => String::from("ok"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn valid_questions_are_read() {
+        let body = r#"[
+            { "question": "**Q** Where is the Doctor from?\na.Skaro\nb.Gallifrey", "answer": "b" },
+            { "question": "**Q** What does TARDIS stand for?\na.Time And Relative Dimension In Space\nb.Time And Space", "answer": "a" }
+        ]"#;
+        assert_eq!(parse_questions(body).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn invalid_questions_are_refused() {
+        assert_eq!(error(parse_questions("[]")), "there are no questions");
+        assert_eq!(
+            error(parse_questions(
+                r#"[{ "question": "Where?\na.Skaro", "answer": "a" }]"#
+            )),
+            "question 1 has no **Q marker"
+        );
+        assert_eq!(
+            error(parse_questions(
+                r#"[{ "question": "**Q** Where?\na.Skaro", "answer": "c" }]"#
+            )),
+            "question 1 has no choice c"
+        );
+        assert_eq!(
+            error(parse_questions(
+                r#"[{ "question": "**Q** Where?\na.Skaro", "answer": "e" }]"#
+            )),
+            "question 1 has no choice e"
+        );
+        let duplicate = r#"[
+            { "question": "**Q** Where?\na.Skaro", "answer": "a" },
+            { "question": "**Q** Where?\na.Skaro", "answer": "a" }
+        ]"#;
+        assert_eq!(
+            error(parse_questions(duplicate)),
+            "question 2 is a duplicate"
+        );
+        assert_ne!(error(parse_questions("{")), "ok");
+    }
+
+    #[test]
+    fn valid_quotes_are_read() {
+        let body = r#"[
+            { "quote": "Allons-y!", "author": "The Tenth Doctor" },
+            { "quote": "Fantastic!", "author": "Ninth Doctor", "doctor": "9", "tags": ["catchphrase"] },
+            { "quote": "Hello, sweetie.", "author": "River Song", "episode": "The Time of Angels" }
+        ]"#;
+        let quotes = parse_quotes(body).unwrap();
+        assert_eq!(quotes.len(), 3);
+        assert_eq!(quotes[0].doctor_id().as_deref(), Some("tenth"));
+        assert_eq!(quotes[2].doctor_id(), None);
+    }
+
+    #[test]
+    fn invalid_quotes_are_refused() {
+        assert_eq!(
+            error(parse_quotes(
+                r#"[{ "quote": " ", "author": "River Song" }]"#
+            )),
+            "quote 1 needs a quote and an author"
+        );
+        assert_eq!(
+            error(parse_quotes(r#"[{ "quote": "Spoilers!", "author": "" }]"#)),
+            "quote 1 needs a quote and an author"
+        );
+        assert_eq!(
+            error(parse_quotes(
+                r#"[{ "quote": "Spoilers!", "author": "River Song", "doctor": "sixteenth" }]"#
+            )),
+            "quote 1 names an unknown Doctor sixteenth"
+        );
+        let duplicate = r#"[
+            { "quote": "Spoilers!", "author": "River Song" },
+            { "quote": " Spoilers! ", "author": "River Song" }
+        ]"#;
+        assert_eq!(error(parse_quotes(duplicate)), "quote 2 is a duplicate");
+    }
+}
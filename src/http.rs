@@ -0,0 +1,190 @@
+use crate::commands::image_policy::check_url;
+use anyhow::Result;
+use reqwest::header::{CONTENT_TYPE, RETRY_AFTER};
+use reqwest::redirect::Policy;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde_json::Value;
+use serenity::prelude::{Context, TypeMapKey};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+const TIMEOUT_SECS: u64 = 10;
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+// Requests in flight at once across the bot, overridable with HTTP_CONCURRENCY
+const DEFAULT_CONCURRENCY: usize = 4;
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_BACKOFF_MS: u64 = 500;
+// Longest Retry-After we are willing to wait inside a command
+const MAX_BACKOFF_SECS: u64 = 10;
+const MAX_REDIRECTS: usize = 5;
+
+// Async HTTP client for the external APIs, shared through ctx.data
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    // Downloads of user-facing URLs, every redirect has to pass the image policy too
+    downloads: Client,
+    limit: Arc<Semaphore>,
+}
+
+impl TypeMapKey for HttpClient {
+    type Value = HttpClient;
+}
+
+impl HttpClient {
+    pub fn new() -> Result<HttpClient> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT_SECS))
+            .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+            .build()?;
+        let downloads = Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT_SECS))
+            .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+            .redirect(Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    return attempt.error(anyhow::anyhow!("too many redirects"));
+                }
+                match check_url(attempt.url().as_str()) {
+                    Ok(_) => attempt.follow(),
+                    Err(e) => attempt.error(e),
+                }
+            }))
+            .build()?;
+        let concurrency = env::var("HTTP_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_CONCURRENCY);
+        Ok(HttpClient {
+            client,
+            downloads,
+            limit: Arc::new(Semaphore::new(concurrency)),
+        })
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    // GET of a URL that came from outside, e.g. an image search result
+    pub fn download(&self, url: &str) -> RequestBuilder {
+        self.downloads.get(url)
+    }
+
+    // Sends the request, None when the resource doesn't exist. Server errors, timeouts and,
+    // unless the API has a quota, rate limits are retried with exponential backoff. The
+    // permit comes with the response, the body counts towards the limit until it is read.
+    async fn send(
+        &self,
+        request: RequestBuilder,
+        quota: bool,
+    ) -> Result<Option<(Response, OwnedSemaphorePermit)>> {
+        let mut attempt = 1;
+        loop {
+            let retry = match request.try_clone() {
+                Some(body) => body,
+                None => return Err(anyhow::anyhow!("Request can't be retried")),
+            };
+            let permit = self.limit.clone().acquire_owned().await?;
+            let rsp = retry.send().await;
+            let retry_after = match rsp {
+                Ok(rsp) if rsp.status() == StatusCode::NOT_FOUND => return Ok(None),
+                // Waiting doesn't bring a used up quota back, the caller moves on instead
+                Ok(rsp) if quota && rsp.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    return Err(anyhow::anyhow!("{} quota is used up", rsp.url()));
+                }
+                Ok(rsp)
+                    if rsp.status() == StatusCode::TOO_MANY_REQUESTS
+                        || rsp.status().is_server_error() =>
+                {
+                    if attempt >= MAX_ATTEMPTS {
+                        return Err(anyhow::anyhow!(
+                            "{} returned {} after {} attempts",
+                            rsp.url(),
+                            rsp.status(),
+                            attempt
+                        ));
+                    }
+                    rsp.headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .map(|secs| Duration::from_secs(secs.min(MAX_BACKOFF_SECS)))
+                }
+                Ok(rsp) => return Ok(Some((rsp.error_for_status()?, permit))),
+                Err(e) if (e.is_timeout() || e.is_connect()) && attempt < MAX_ATTEMPTS => None,
+                Err(e) => return Err(e.into()),
+            };
+            drop(permit);
+            let backoff = retry_after.unwrap_or(Duration::from_millis(
+                BASE_BACKOFF_MS * 2u64.pow(attempt - 1),
+            ));
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    // Reads a JSON body, None when the resource doesn't exist
+    pub async fn json(&self, request: RequestBuilder) -> Result<Option<Value>> {
+        match self.send(request, false).await? {
+            Some((rsp, _permit)) => Ok(Some(rsp.json().await?)),
+            None => Ok(None),
+        }
+    }
+
+    // Reads a JSON body from an API with a request quota such as RapidAPI, where a 429
+    // fails right away
+    pub async fn quota_json(&self, request: RequestBuilder) -> Result<Option<Value>> {
+        match self.send(request, true).await? {
+            Some((rsp, _permit)) => Ok(Some(rsp.json().await?)),
+            None => Ok(None),
+        }
+    }
+
+    // Reads a raw body of at most max_bytes with its content type, None when the resource
+    // doesn't exist. Bodies over the limit are dropped without reading them to the end.
+    pub async fn bytes(
+        &self,
+        request: RequestBuilder,
+        max_bytes: usize,
+    ) -> Result<Option<(Vec<u8>, Option<String>)>> {
+        let (mut rsp, _permit) = match self.send(request, false).await? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        let too_large = || anyhow::anyhow!("body is over the limit of {} bytes", max_bytes);
+        if rsp
+            .content_length()
+            .is_some_and(|len| len > max_bytes as u64)
+        {
+            return Err(too_large());
+        }
+        let content_type = rsp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let mut body: Vec<u8> = Vec::new();
+        while let Some(chunk) = rsp.chunk().await? {
+            if body.len() + chunk.len() > max_bytes {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(Some((body, content_type)))
+    }
+}
+
+pub async fn http_client(ctx: &Context) -> Result<HttpClient> {
+    let data = ctx.data.read().await;
+    match data.get::<HttpClient>() {
+        Some(body) => Ok(body.clone()),
+        None => Err(anyhow::anyhow!("HTTP client is missing from the context")),
+    }
+}
@@ -1,13 +1,44 @@
 use crate::commands::points::send_trivia;
+use anyhow::Result;
 use clap::{Parser, Subcommand};
-use commands::doctor::doctor_cmd_response;
-use commands::episode::{episode_cmd_response, Episode};
-use commands::points::{points_cmd_response, update_user_points, Question};
-use commands::quote::quote_cmd_response;
+use commands::browse::{
+    browse_button_response, episodes_cmd_response, onthisday_cmd_response, season_cmd_response,
+};
+use commands::daily::{daily_button_response, daily_cmd_response, post_daily_summaries};
+use commands::doctor::{
+    blockimage_cmd_response, doctor_autocomplete, doctor_button_response, doctor_cmd_response,
+    warm_image_cache,
+};
+use commands::episode::{
+    backfill_catalog, episode_autocomplete, episode_cast, episode_cmd_response,
+    episode_select_response, Episode,
+};
+use commands::gallery::{load_gallery, GALLERY_DIR};
+use commands::metadata::prefetch_metadata;
+use commands::points::{
+    find_question, points_cmd_response, trivia_category, trivia_cmd_response, update_user_points,
+    Category,
+};
+use commands::practice::practice_message;
+use commands::quote::{
+    post_quotes_of_the_day, quote_button_response, quote_cmd_response, quote_modal_response,
+    quoteoftheday_cmd_response, quotes_cmd_response, MAX_SEARCH_LEN,
+};
+use commands::team::{team_cmd_response, update_team_points};
+use commands::tournament::{get_tournament, record_answer, tournament_cmd, DEFAULT_ROUNDS};
+use commands::watch::{
+    watch_cmd_response, watch_reroll_refusal, watch_reroll_response, watch_seen_response,
+    watched_cmd_response, watchlist_cmd_response,
+};
+use content::{
+    read_questions, read_quotes, reload_cmd_response, reload_content, watch_content,
+    QUESTIONS_FILE, QUOTES_FILE,
+};
 use dotenv::dotenv;
-use rusqlite::{params, Connection, Statement};
-use serenity::all::ResolvedValue::{self, Integer};
-use serenity::all::{ChannelType, CommandOptionType, Interaction, Message};
+use http::{http_client, HttpClient};
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::all::ResolvedValue;
+use serenity::all::{ChannelType, CommandOptionType, Interaction, Message, Permissions};
 use serenity::async_trait;
 use serenity::builder::{
     CreateCommand, CreateCommandOption, CreateInteractionResponse,
@@ -16,10 +47,16 @@ use serenity::builder::{
 use serenity::model::gateway::Ready;
 use serenity::prelude::*;
 use serenity::Client;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::{env, fs};
 use tokio::time::sleep;
 mod commands;
+mod content;
+mod http;
 
 // Verify integrity function
 fn verify_integrity() -> u8 {
@@ -51,12 +88,16 @@ fn verify_integrity() -> u8 {
             verify_integrity += 1;
         }
     }
-    match fs::read_to_string("questions.json") {
-        Ok(_) => {
-            println!("✅questions.json found");
+    match read_questions() {
+        Ok(questions) => {
+            println!(
+                "✅{} is valid ({} questions)",
+                QUESTIONS_FILE,
+                questions.len()
+            );
         }
-        Err(_) => {
-            println!("❌questions.json not found");
+        Err(e) => {
+            println!("❌{} is invalid: {}", QUESTIONS_FILE, e);
             verify_integrity += 1;
         }
     }
@@ -69,21 +110,54 @@ fn verify_integrity() -> u8 {
             verify_integrity += 1;
         }
     }
-    match fs::read_to_string("quotes.json") {
-        Ok(_) => {
-            println!("✅quotes.json found");
+    match read_quotes() {
+        Ok((quotes, _)) => {
+            println!("✅{} is valid ({} quotes)", QUOTES_FILE, quotes.len());
         }
-        Err(_) => {
-            println!("❌quotes.json not found, Bot can't send quotes");
+        Err(e) => {
+            println!("❌{} is invalid, Bot can't send quotes: {}", QUOTES_FILE, e);
             verify_integrity += 1;
         }
     }
+    // The gallery is optional, /doctor falls back to image search or text without it
+    match load_gallery() {
+        Ok(gallery) => {
+            let images: usize = gallery.iter().map(|entry| entry.images.len()).sum();
+            println!(
+                "✅gallery/gallery.json found ({} Doctors, {} images)",
+                gallery.len(),
+                images
+            );
+            if images == 0 {
+                println!("gallery/gallery.json lists no pictures yet, see gallery/README.md to add licensed ones");
+            }
+            for entry in &gallery {
+                for image in &entry.images {
+                    if !Path::new(GALLERY_DIR).join(&image.file).is_file() {
+                        println!("gallery image {} is missing", image.file);
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            println!(
+                "gallery/gallery.json couldn't be read, /doctor has no curated pictures: {}",
+                e
+            );
+        }
+    }
     verify_integrity
 }
 
+// Curated classic era catalog, imported at startup when present
+const CLASSIC_CSV: &str = "classic_serials.csv";
+
 // Handler for gateway events
 struct Handler;
 
+// Ready fires again on reconnects, background jobs must only be spawned once
+static JOBS_STARTED: AtomicBool = AtomicBool::new(false);
+
 // Parser for cmd line arguments
 #[derive(Parser, Debug)]
 #[command(version, about = "Discord Bot for Dr. Who Trivia")]
@@ -96,27 +170,134 @@ struct Args {
 enum Subcmd {
     HelpBot,
     Verify,
+    /// Imports a CSV of classic serials into the episode catalog
+    Import {
+        /// Path of the CSV file
+        path: String,
+    },
+    /// Fetches episode metadata into the local cache
+    Prefetch {
+        /// Refetch entries that are still fresh
+        #[arg(long)]
+        force: bool,
+    },
+    /// Downloads Doctor pictures from the image search into the image cache
+    WarmCache {
+        /// Search again for Doctors that are already cached
+        #[arg(long)]
+        force: bool,
+    },
 }
 // Prepares commands to be deployed to the Discord API
 fn prepare_commands() -> Vec<CreateCommand> {
     let mut cmds: Vec<CreateCommand> = Vec::new();
     // Quote command
-    let quote_cmd = CreateCommand::new("quote").description("Sends a Dr. Who quote");
+    let quote_cmd = CreateCommand::new("quote")
+        .description("Sends a Dr. Who quote")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "doctor", "quotes of this Doctor")
+                .set_autocomplete(true),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "speaker",
+            "who said it, e.g. Rory",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "keyword",
+            "word in the quote, its episode or tags",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "search",
+                "lists every quote matching the text",
+            )
+            .max_length(MAX_SEARCH_LEN),
+        );
+
+    // Quote submission command
+    let quotes_cmd = CreateCommand::new("quotes")
+        .description("Submits and reviews quotes")
+        .dm_permission(false)
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "add",
+            "Submits a quote for the moderators to review",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "queue",
+            "Reviews submitted quotes (Manage Messages)",
+        ));
+
+    // Reload command, for administrators
+    let reload_cmd = CreateCommand::new("reload")
+        .description("Reloads questions.json and quotes.json")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .dm_permission(false);
+
+    // Quote of the day command, for server managers
+    let quoteoftheday_cmd = CreateCommand::new("quoteoftheday")
+        .description("Posts a quote every day in a channel")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .dm_permission(false)
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "set",
+                "Sets the channel and time of the quote of the day",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Channel,
+                    "channel",
+                    "channel to post in",
+                )
+                .channel_types(vec![ChannelType::Text])
+                .required(true),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::String, "time", "HH:MM in UTC")
+                    .required(true),
+            ),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "off",
+            "Stops the quote of the day",
+        ));
 
     // Doctor command
-    let doctor_cmd_option =
-        CreateCommandOption::new(CommandOptionType::Integer, "n", "the number of the doctor")
-            .required(true)
-            .min_int_value(1)
-            .max_int_value(14);
+    let doctor_cmd_option = CreateCommandOption::new(
+        CommandOptionType::String,
+        "doctor",
+        "number or name of the doctor, e.g. 10, war or fugitive",
+    )
+    .required(true)
+    .set_autocomplete(true);
     let doctor_cmd = CreateCommand::new("doctor")
-        .description("Sends a pictore of the n-th doctor")
-        .add_option(doctor_cmd_option);
+        .description("Shows the profile of a doctor")
+        .add_option(doctor_cmd_option.clone());
+
+    // Block image command, for moderators
+    let blockimage_cmd = CreateCommand::new("blockimage")
+        .description("Blocks the cached search picture of a doctor")
+        .default_member_permissions(Permissions::MANAGE_MESSAGES)
+        .dm_permission(false)
+        .add_option(doctor_cmd_option)
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "reason",
+            "why the picture is blocked",
+        ));
 
     // Episode command
     let episode_cmd_option =
         CreateCommandOption::new(CommandOptionType::String, "name", "title of episode")
-            .required(true);
+            .required(true)
+            .set_autocomplete(true);
     let episode_cmd = CreateCommand::new("episode")
         .description("Search for a specific Doctor Who episode")
         .add_option(episode_cmd_option);
@@ -124,61 +305,359 @@ fn prepare_commands() -> Vec<CreateCommand> {
     // Points command
     let points = CreateCommand::new("points")
         .description("Shows the number of points user have on this guild");
+
+    // Trivia category command, anyone can see the category but only server managers change it
+    let mut trivia_category_option = CreateCommandOption::new(
+        CommandOptionType::String,
+        "category",
+        "kind of questions regular rounds ask",
+    );
+    for category in Category::ALL {
+        trivia_category_option =
+            trivia_category_option.add_string_choice(category.as_str(), category.as_str());
+    }
+    let trivia_cmd = CreateCommand::new("trivia")
+        .description("Shows or sets the kind of trivia questions asked in this server")
+        .dm_permission(false)
+        .add_option(trivia_category_option);
+
+    // Tournament command
+    let rounds_option = CreateCommandOption::new(
+        CommandOptionType::Integer,
+        "rounds",
+        "number of questions in the match",
+    )
+    .min_int_value(1)
+    .max_int_value(50);
+    let mut category_option = CreateCommandOption::new(
+        CommandOptionType::String,
+        "category",
+        "kind of questions, general trivia by default",
+    );
+    for category in Category::ALL {
+        category_option = category_option.add_string_choice(category.as_str(), category.as_str());
+    }
+    let tournament_cmd = CreateCommand::new("tournament")
+        .description("Runs a trivia tournament in this channel")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "start",
+                format!("Opens registration ({} rounds by default)", DEFAULT_ROUNDS),
+            )
+            .add_sub_option(rounds_option)
+            .add_sub_option(category_option),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "join",
+            "Registers you for the tournament",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "begin",
+            "Closes registration and starts the first round",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "skip",
+            "Skips the current question without scoring it (host only)",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "cancel",
+            "Cancels the tournament",
+        ));
+
+    // Team command
+    let team_name_option =
+        CreateCommandOption::new(CommandOptionType::String, "name", "name of the team")
+            .required(true);
+    let team_cmd = CreateCommand::new("team")
+        .description("Play trivia as a team")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::SubCommand, "create", "Creates a team")
+                .add_sub_option(team_name_option.clone())
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::Role,
+                    "role",
+                    "members with this role play for the team (Manage Roles)",
+                )),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::SubCommand, "join", "Joins a team")
+                .add_sub_option(team_name_option),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "leave",
+            "Leaves your team",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "leaderboard",
+                "Shows the team leaderboard",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "season", "season to show")
+                    .min_int_value(1),
+            ),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "reset",
+            "Starts a new season of team scores",
+        ));
+
+    // Daily command
+    let daily_cmd = CreateCommand::new("daily")
+        .description("Answer today's daily challenge question and keep your streak");
+
+    // Episode browsing commands
+    let season_cmd = CreateCommand::new("season")
+        .description("Lists the episodes of a season")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "n", "the season number")
+                .required(true)
+                .min_int_value(1),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "era", "which series to list")
+                .add_string_choice("Revived series (2005-)", "revival")
+                .add_string_choice("Classic series (1963-1989)", "classic"),
+        );
+    let episodes_cmd = CreateCommand::new("episodes")
+        .description("Lists the episodes of a Doctor")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "doctor",
+                "the number of the doctor",
+            )
+            .required(true)
+            .min_int_value(1)
+            .max_int_value(15),
+        );
+    let onthisday_cmd = CreateCommand::new("onthisday")
+        .description("Shows the episodes that first aired on today's date");
+
+    // Watch command
+    let watch_cmd = CreateCommand::new("watch")
+        .description("Recommends a random episode to watch")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "season", "only this season")
+                .min_int_value(1),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "doctor", "only this doctor")
+                .min_int_value(1)
+                .max_int_value(15),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Number, "rating", "minimum IMDb rating")
+                .min_number_value(0.0)
+                .max_number_value(10.0),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "new",
+            "only episodes not yet recommended to you",
+        ));
+
+    // Watchlist and watched commands
+    let watch_name_option =
+        CreateCommandOption::new(CommandOptionType::String, "name", "name of the episode")
+            .required(true)
+            .set_autocomplete(true);
+    let watchlist_cmd = CreateCommand::new("watchlist")
+        .description("Keeps a list of episodes you want to watch")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "add",
+                "Adds an episode to your watchlist",
+            )
+            .add_sub_option(watch_name_option.clone()),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "remove",
+                "Removes an episode from your watchlist",
+            )
+            .add_sub_option(watch_name_option.clone()),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "list",
+            "Shows your watchlist",
+        ));
+    let watched_cmd = CreateCommand::new("watched")
+        .description("Tracks the episodes you have watched")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "add",
+                "Marks an episode as watched",
+            )
+            .add_sub_option(watch_name_option.clone()),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "remove",
+                "Marks an episode as unwatched",
+            )
+            .add_sub_option(watch_name_option),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "progress",
+                "Shows how much of each season you have watched",
+            )
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "member",
+                "compare your progress with this member",
+            )),
+        );
     cmds.push(quote_cmd);
+    cmds.push(quotes_cmd);
+    cmds.push(quoteoftheday_cmd);
+    cmds.push(reload_cmd);
     cmds.push(doctor_cmd);
+    cmds.push(blockimage_cmd);
     cmds.push(episode_cmd);
     cmds.push(points);
+    cmds.push(trivia_cmd);
+    cmds.push(tournament_cmd);
+    cmds.push(team_cmd);
+    cmds.push(daily_cmd);
+    cmds.push(season_cmd);
+    cmds.push(episodes_cmd);
+    cmds.push(onthisday_cmd);
+    cmds.push(watch_cmd);
+    cmds.push(watchlist_cmd);
+    cmds.push(watched_cmd);
     cmds
 }
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
+        // Direct messages are a solo practice mode with their own score
+        if msg.guild_id.is_none() {
+            match practice_message(&ctx, &msg).await {
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Failed to handle practice message: {}", e);
+                }
+            }
+            return;
+        }
         let client_token = env::var("CLIENT_ID").unwrap();
         if let Some(mut msg_reply) = msg.referenced_message {
             if msg_reply.author.id.to_string() == client_token && msg_reply.content.contains("**Q")
             {
-                let str = fs::read_to_string("questions.json").unwrap();
-                let questions: Vec<Question> = match serde_json::from_str(&str) {
-                    Ok(body) => body,
+                let question = match find_question(&msg_reply.content) {
+                    Ok(Some(body)) => body,
+                    Ok(None) => return,
                     Err(e) => {
                         println!("Failed to parse questions.json: {}", e);
                         return;
                     }
                 };
-                for question in &questions {
-                    if question.question == msg_reply.content
-                        && question.answer == msg.content.to_ascii_lowercase()
-                    {
-                        let new_msg = msg_reply.content.replace("**", "__");
-                        // Update user points
-                        let guild_id = match msg.guild_id {
-                            Some(body) => body.to_string(),
-                            None => String::from(""),
-                        };
-                        match update_user_points(&msg.author.name, guild_id) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!("Failed to update user points: {}", e);
-                            }
+                let correct = question.answer == msg.content.to_ascii_lowercase();
+                // Tournament questions only count for registered players, once per question
+                let channel = msg.channel_id.to_string();
+                let tournament = match get_tournament(&channel) {
+                    Ok(body) => body.filter(|t| t.is_playing()),
+                    Err(e) => {
+                        println!("Failed to get tournament: {}", e);
+                        None
+                    }
+                };
+                if let Some(t) = &tournament {
+                    // Replies to earlier rounds' questions don't count
+                    if !t.is_current(&msg_reply.id.to_string()) {
+                        return;
+                    }
+                    match t.attempt(&msg.author.id.to_string()) {
+                        Ok(true) => {}
+                        Ok(false) => return,
+                        Err(e) => {
+                            println!("Failed to record tournament attempt: {}", e);
+                            return;
                         }
-                        msg_reply
-                            .edit(&ctx.http, EditMessage::new().content(new_msg))
+                    }
+                }
+                if !correct {
+                    return;
+                }
+                let new_msg = msg_reply.content.replace("**", "__");
+                msg_reply
+                    .edit(&ctx.http, EditMessage::new().content(new_msg))
+                    .await
+                    .unwrap_or(());
+                let guild_channel = match msg.channel_id.to_channel(&ctx).await {
+                    Ok(body) => body.guild(),
+                    Err(e) => {
+                        println!("Failed to get channel: {}", e);
+                        None
+                    }
+                };
+                if let Some(t) = tournament {
+                    if let Some(guild_channel_id) = guild_channel {
+                        match record_answer(t, &msg.author.id.to_string(), &guild_channel_id, &ctx)
                             .await
-                            .unwrap_or(());
-                        // Sends trivia question
-                        sleep(Duration::from_secs(3)).await;
-                        if let Some(guild_channel_id) =
-                            msg.channel_id.to_channel(&ctx).await.unwrap().guild()
                         {
-                            match send_trivia(&guild_channel_id, &ctx).await {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    println!("Failed to send trivia: {}", e);
-                                }
+                            Ok(_) => {}
+                            Err(e) => {
+                                println!("Failed to record tournament answer: {}", e);
                             }
                         }
                     }
+                    return;
+                }
+                // Update user points
+                let guild_id = match msg.guild_id {
+                    Some(body) => body.to_string(),
+                    None => String::from(""),
+                };
+                let roles = match &msg.member {
+                    Some(member) => member.roles.clone(),
+                    None => Vec::new(),
+                };
+                match update_team_points(&guild_id, &msg.author.id.to_string(), &roles) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Failed to update team points: {}", e);
+                    }
+                }
+                match update_user_points(&msg.author.name, guild_id.clone()) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Failed to update user points: {}", e);
+                    }
+                }
+                // Sends trivia question, of the kind the guild plays
+                let category = match trivia_category(&guild_id) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("Failed to get trivia category: {}", e);
+                        Category::General
+                    }
+                };
+                sleep(Duration::from_secs(3)).await;
+                if let Some(guild_channel_id) = guild_channel {
+                    match send_trivia(&guild_channel_id, category, &ctx).await {
+                        Ok(_) => {}
+                        Err(e) => {
+                            println!("Failed to send trivia: {}", e);
+                        }
+                    }
                 }
             }
         }
@@ -203,8 +682,15 @@ impl EventHandler for Handler {
             };
             for (_id, channel) in channels {
                 if channel.kind == ChannelType::Text && channel.name == "general" {
-                    // Sends trivia question
-                    match send_trivia(&channel, &ctx).await {
+                    // Sends trivia question, of the kind the guild plays
+                    let category = match trivia_category(&guild.to_string()) {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Failed to get trivia category: {}", e);
+                            Category::General
+                        }
+                    };
+                    match send_trivia(&channel, category, &ctx).await {
                         Ok(_) => {}
                         Err(e) => {
                             println!("Failed to send trivia: {}", e);
@@ -214,28 +700,248 @@ impl EventHandler for Handler {
             }
         }
 
+        // Posts yesterday's daily challenge results once the UTC day is over, and each
+        // guild's quote of the day once its time has come. Runs every minute so quotes
+        // go out on time.
+        if !JOBS_STARTED.swap(true, Ordering::SeqCst) {
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                loop {
+                    match post_daily_summaries(ctx.cache.guilds(), &ctx).await {
+                        Ok(_) => {}
+                        Err(e) => {
+                            println!("Failed to post daily summaries: {}", e);
+                        }
+                    }
+                    match post_quotes_of_the_day(&ctx).await {
+                        Ok(_) => {}
+                        Err(e) => {
+                            println!("Failed to post quotes of the day: {}", e);
+                        }
+                    }
+                    sleep(Duration::from_secs(60)).await;
+                }
+            });
+        }
+
         println!("{} is connected!", ready.user.name);
     }
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::Autocomplete(ac) = &interaction {
+            if matches!(ac.data.name.as_str(), "episode" | "watchlist" | "watched") {
+                match episode_autocomplete(ac) {
+                    Ok(body) => {
+                        ac.create_response(&ctx, CreateInteractionResponse::Autocomplete(body))
+                            .await
+                            .unwrap_or(());
+                    }
+                    Err(e) => {
+                        println!("Failed to autocomplete episode: {}", e);
+                    }
+                }
+            } else if ["doctor", "blockimage", "quote"].contains(&ac.data.name.as_str()) {
+                let body = doctor_autocomplete(ac);
+                ac.create_response(&ctx, CreateInteractionResponse::Autocomplete(body))
+                    .await
+                    .unwrap_or(());
+            }
+            return;
+        }
+        if let Interaction::Modal(modal) = &interaction {
+            if modal.data.custom_id == "quote:add" {
+                let response = match quote_modal_response(modal) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("Failed to submit quote: {}", e);
+                        CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::new()
+                                .content("Failed to submit the quote, please try again later")
+                                .ephemeral(true),
+                        )
+                    }
+                };
+                modal.create_response(&ctx, response).await.unwrap_or(());
+            }
+            return;
+        }
+        if let Interaction::Component(component) = &interaction {
+            if component.data.custom_id.starts_with("daily:") {
+                let response = match daily_button_response(component) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::new()
+                                .content("Failed to record your answer, please try again later")
+                                .ephemeral(true),
+                        )
+                    }
+                };
+                component
+                    .create_response(&ctx, response)
+                    .await
+                    .unwrap_or(());
+            } else if component.data.custom_id.starts_with("quote:") {
+                let response = match quote_button_response(component) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        CreateInteractionResponse::Acknowledge
+                    }
+                };
+                component
+                    .create_response(&ctx, response)
+                    .await
+                    .unwrap_or(());
+            } else if component.data.custom_id.starts_with("browse:") {
+                let response = match browse_button_response(component) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        CreateInteractionResponse::Acknowledge
+                    }
+                };
+                component
+                    .create_response(&ctx, response)
+                    .await
+                    .unwrap_or(());
+            } else if component.data.custom_id.starts_with("watch:reroll:") {
+                if let Some(refusal) = watch_reroll_refusal(component) {
+                    component.create_response(&ctx, refusal).await.unwrap_or(());
+                    return;
+                }
+                match component.defer(&ctx.http).await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Failed to defer component: {}", e);
+                    }
+                }
+                let response = match http_client(&ctx).await {
+                    Ok(http) => watch_reroll_response(component, &http).await,
+                    Err(e) => Err(e),
+                };
+                let response = match response {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        EditInteractionResponse::new()
+                            .content("Failed to recommend an episode, please try again later")
+                    }
+                };
+                component
+                    .edit_response(&ctx, response)
+                    .await
+                    .unwrap_or(serenity::all::Message::default());
+            } else if component.data.custom_id.starts_with("watch:") {
+                let response = match watch_seen_response(component) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::new()
+                                .content(
+                                    "Failed to update your watch history, please try again later",
+                                )
+                                .ephemeral(true),
+                        )
+                    }
+                };
+                component
+                    .create_response(&ctx, response)
+                    .await
+                    .unwrap_or(());
+            } else if component.data.custom_id.starts_with("doctor:") {
+                match component.defer(&ctx.http).await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Failed to defer component: {}", e);
+                    }
+                }
+                let response = match doctor_button_response(component, &ctx).await {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        EditInteractionResponse::new()
+                            .content("Failed to get the Doctor, please try again later")
+                    }
+                };
+                component
+                    .edit_response(&ctx, response)
+                    .await
+                    .unwrap_or(serenity::all::Message::default());
+            } else if component.data.custom_id == "episode_select"
+                || component.data.custom_id == "doctor_episode"
+            {
+                // Episodes picked from a Doctor profile don't replace the profile
+                let deferred = if component.data.custom_id == "doctor_episode" {
+                    component.defer_ephemeral(&ctx.http).await
+                } else {
+                    component.defer(&ctx.http).await
+                };
+                match deferred {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Failed to defer component: {}", e);
+                    }
+                }
+                let response = match http_client(&ctx).await {
+                    Ok(http) => episode_select_response(component, &http).await,
+                    Err(e) => Err(e),
+                };
+                let response = match response {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        EditInteractionResponse::new()
+                            .content("Failed to get episode, please try again later")
+                    }
+                };
+                component
+                    .edit_response(&ctx, response)
+                    .await
+                    .unwrap_or(serenity::all::Message::default());
+            }
+            return;
+        }
         if let Interaction::Command(cmd) = interaction {
             match cmd.data.name.as_str() {
                 "quote" => {
-                    let response = quote_cmd_response();
+                    let response = match quote_cmd_response(&cmd) {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Failed to send a quote: {}", e);
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content("Couldn't find a quote right now"),
+                            )
+                        }
+                    };
                     cmd.create_response(&ctx, response).await.unwrap_or(());
                 }
-                "doctor" => {
-                    let num = cmd.data.options()[0].value.clone();
-                    let mut dr_nr = 0u8;
-                    match num {
-                        Integer(n) => {
-                            dr_nr = n as u8;
+                "quotes" => {
+                    let response = match quotes_cmd_response(&cmd) {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Failed to handle quotes: {}", e);
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content("Failed to open the quotes, please try again later")
+                                    .ephemeral(true),
+                            )
                         }
+                    };
+                    cmd.create_response(&ctx, response).await.unwrap_or(());
+                }
+                "doctor" => {
+                    let doctor = match cmd.data.options().first().map(|o| o.value.clone()) {
+                        Some(ResolvedValue::String(s)) => s.to_string(),
                         _ => {
                             println!("Failed to parse doctor command");
+                            String::new()
                         }
-                    }
+                    };
                     cmd.defer(&ctx.http).await.expect("Failed to defer");
-                    let response = doctor_cmd_response(dr_nr, &ctx).await;
+                    let response = doctor_cmd_response(&doctor, cmd.guild_id, &ctx).await;
                     cmd.edit_response(&ctx, response)
                         .await
                         .unwrap_or(serenity::all::Message::default());
@@ -263,7 +969,10 @@ impl EventHandler for Handler {
                             .unwrap_or(serenity::all::Message::default());
                         }
                     }
-                    let ok = episode_cmd_response(episode_name).await;
+                    let ok = match http_client(&ctx).await {
+                        Ok(http) => episode_cmd_response(episode_name, &http).await,
+                        Err(e) => Err(e),
+                    };
                     let response = match ok {
                         Ok(body) => body,
                         Err(_) => EditInteractionResponse::new()
@@ -286,64 +995,518 @@ impl EventHandler for Handler {
                     };
                     cmd.create_response(&ctx, response).await.unwrap_or(());
                 }
+                "trivia" => {
+                    let response = match trivia_cmd_response(&cmd) {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content(
+                                        "Failed to set the trivia category, please try again later",
+                                    )
+                                    .ephemeral(true),
+                            )
+                        }
+                    };
+                    cmd.create_response(&ctx, response).await.unwrap_or(());
+                }
+                "daily" => {
+                    let response = match daily_cmd_response(&cmd) {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content(
+                                        "Failed to get the daily question, please try again later",
+                                    )
+                                    .ephemeral(true),
+                            )
+                        }
+                    };
+                    cmd.create_response(&ctx, response).await.unwrap_or(());
+                }
+                "season" | "episodes" | "onthisday" => {
+                    let response = match cmd.data.name.as_str() {
+                        "season" => season_cmd_response(&cmd),
+                        "episodes" => episodes_cmd_response(&cmd),
+                        _ => onthisday_cmd_response(),
+                    };
+                    let response = match response {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content("Failed to list episodes, please try again later"),
+                            )
+                        }
+                    };
+                    cmd.create_response(&ctx, response).await.unwrap_or(());
+                }
+                "reload" => {
+                    let response = reload_cmd_response(&cmd).await;
+                    cmd.create_response(&ctx, response).await.unwrap_or(());
+                }
+                "quoteoftheday" => {
+                    let response = match quoteoftheday_cmd_response(&cmd) {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content("Failed to set up the quote of the day, please try again later")
+                                    .ephemeral(true),
+                            )
+                        }
+                    };
+                    cmd.create_response(&ctx, response).await.unwrap_or(());
+                }
+                "blockimage" => {
+                    let response = match blockimage_cmd_response(&cmd) {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content("Failed to block the picture, please try again later")
+                                    .ephemeral(true),
+                            )
+                        }
+                    };
+                    cmd.create_response(&ctx, response).await.unwrap_or(());
+                }
+                "watch" => {
+                    match cmd.defer(&ctx.http).await {
+                        Ok(_) => {}
+                        Err(e) => {
+                            println!("Failed to defer command: {}", e);
+                        }
+                    }
+                    let response = match http_client(&ctx).await {
+                        Ok(http) => watch_cmd_response(&cmd, &http).await,
+                        Err(e) => Err(e),
+                    };
+                    let response = match response {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            EditInteractionResponse::new()
+                                .content("Failed to recommend an episode, please try again later")
+                        }
+                    };
+                    cmd.edit_response(&ctx, response)
+                        .await
+                        .unwrap_or(serenity::all::Message::default());
+                }
+                "watchlist" | "watched" => {
+                    let response = match cmd.data.name.as_str() {
+                        "watchlist" => watchlist_cmd_response(&cmd),
+                        _ => watched_cmd_response(&cmd),
+                    };
+                    let response = match response {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new().content(
+                                    "Failed to update your watch history, please try again later",
+                                ),
+                            )
+                        }
+                    };
+                    cmd.create_response(&ctx, response).await.unwrap_or(());
+                }
+                "team" => {
+                    let response = match team_cmd_response(&cmd) {
+                        Ok(body) => body,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content("Failed to run team command, please try again later"),
+                            )
+                        }
+                    };
+                    cmd.create_response(&ctx, response).await.unwrap_or(());
+                }
+                "tournament" => match tournament_cmd(&cmd, &ctx).await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Failed to run tournament command: {}", e);
+                    }
+                },
                 _ => {}
             }
         }
     }
 }
 
-fn populate_database(conn: &Connection) {
-    let read_dir = fs::read_to_string("episodes.json");
-    match read_dir {
-        Ok(body) => {
-            let episodes: Vec<Episode> = serde_json::from_str(&body).unwrap_or(Vec::new());
-            for episode in episodes {
-                conn.execute(
-                    "insert into episodes (id, title,season,episode) values (?1, ?2, ?3, ?4)",
-                    params![episode.id, episode.title, episode.season, episode.episode],
-                )
-                .unwrap_or(0);
-            }
+// Hash of the source a catalog entry was last synced from
+fn synced_hash(conn: &Connection, key: &str) -> Result<Option<String>> {
+    let synced: Option<String> = conn
+        .query_row(
+            "select value from catalog_meta where key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(synced)
+}
+
+// Upserts the episodes of a source file into the catalog and records its hash. Episodes
+// the file no longer lists are dropped.
+fn upsert_episodes(
+    conn: &mut Connection,
+    episodes: Vec<Episode>,
+    source: &str,
+    key: &str,
+    hash: &str,
+) -> Result<usize> {
+    // Sources may list an episode more than once, keep the first entry of each
+    let mut seen: HashSet<String> = HashSet::new();
+    let tx = conn.transaction()?;
+    let mut count = 0;
+    for mut episode in episodes {
+        if !seen.insert(episode.id.clone()) {
+            continue;
         }
-        Err(e) => {
-            println!("Couldn't find episodes.json: {}", e);
+        if episode.doctor.is_none() {
+            episode.doctor = episode_cast(&episode).map(|(doctor, _)| doctor);
         }
+        // Revival specials are numbered as episode 0 of the following season
+        let special = episode.special || (episode.era == "revival" && episode.episode == 0);
+        tx.execute(
+            "insert into episodes (id, title, season, episode, era, doctor, air_date, story_code, part, special, source)
+            values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            on conflict(id) do update set title = excluded.title,
+                season = excluded.season, episode = excluded.episode,
+                era = excluded.era, doctor = excluded.doctor,
+                air_date = coalesce(excluded.air_date, episodes.air_date),
+                story_code = excluded.story_code, part = excluded.part, special = excluded.special,
+                source = excluded.source",
+            params![
+                episode.id,
+                episode.title,
+                episode.season,
+                episode.episode,
+                episode.era,
+                episode.doctor,
+                episode.air_date,
+                episode.story_code,
+                episode.part,
+                special,
+                source
+            ],
+        )?;
+        count += 1;
     }
-    let mut stmt: Statement<'_>;
-    match conn.prepare("select * from episodes") {
-        Ok(body) => {
-            stmt = body;
-        }
-        Err(e) => {
-            println!("couldn't populate database: {}", e);
-            return;
-        }
-    };
-    let try_episodes_iter = stmt.query_map([], |row| {
-        Ok(Episode {
-            id: row.get(0).unwrap(),
-            title: row.get(1).unwrap(),
-            season: row.get(2).unwrap(),
-            episode: row.get(3).unwrap(),
-        })
-    });
-    match try_episodes_iter {
-        Ok(_) => {}
-        Err(_) => {
-            return;
-        }
-    };
-    let episodes_iter = try_episodes_iter.unwrap();
-    for episode in episodes_iter {
-        match episode {
-            Ok(_) => {
-                //println!("{} {}", _episode.id, _episode.title);
-            }
+    let ids: Vec<&String> = seen.iter().collect();
+    tx.execute(
+        "delete from episodes where source = ?1 and id not in (select value from json_each(?2))",
+        params![source, serde_json::to_string(&ids)?],
+    )?;
+    tx.execute(
+        "insert into catalog_meta (key, value) values (?1, ?2)
+        on conflict(key) do update set value = excluded.value",
+        params![key, hash],
+    )?;
+    tx.commit()?;
+    Ok(count)
+}
+
+// Loads episodes.json into the catalog, skipped when the file is unchanged since the last sync
+fn populate_database(conn: &mut Connection) -> Result<()> {
+    let body = fs::read_to_string("episodes.json")?;
+    let hash = format!("{:x}", Sha256::digest(body.as_bytes()));
+    if synced_hash(conn, "episodes_hash")?.as_deref() == Some(hash.as_str()) {
+        println!("✅Episode catalog is up to date");
+        return Ok(());
+    }
+    let episodes: Vec<Episode> = serde_json::from_str(&body)?;
+    let count = upsert_episodes(conn, episodes, "episodes.json", "episodes_hash", &hash)?;
+    println!("✅Synced {} episodes from episodes.json", count);
+    Ok(())
+}
+
+// Imports a curated CSV of classic serials, one row per part. Unless forced, an
+// unchanged file is skipped.
+fn import_classic_csv(conn: &mut Connection, path: &str, force: bool) -> Result<()> {
+    let body = fs::read_to_string(path)?;
+    let hash = format!("{:x}", Sha256::digest(body.as_bytes()));
+    let key = format!("csv_hash:{}", path);
+    if !force && synced_hash(conn, &key)?.as_deref() == Some(hash.as_str()) {
+        println!("✅{} is up to date", path);
+        return Ok(());
+    }
+    let mut reader = csv::Reader::from_reader(body.as_bytes());
+    let mut episodes: Vec<Episode> = Vec::new();
+    for (line, record) in reader.deserialize().enumerate() {
+        match record {
+            Ok(episode) => episodes.push(episode),
             Err(e) => {
-                println!("Couldn't find episodes.json: {}", e);
+                return Err(anyhow::anyhow!("{} row {}: {}", path, line + 1, e));
             }
         }
     }
+    let count = upsert_episodes(conn, episodes, path, &key, &hash)?;
+    println!("✅Imported {} episodes from {}", count, path);
+    Ok(())
+}
+
+// Adds a column to catalogs created before it existed
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("pragma table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+    if !columns.iter().any(|c| c == column) {
+        conn.execute(
+            &format!("alter table {} add column {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+fn prepare_episodes_database(conn: &Connection) -> Result<()> {
+    let create = r"
+    create table if not exists episodes(
+        id text not null primary key,
+        title text not null,
+        season integer not null,
+        episode integer not null,
+        era text not null default 'revival',
+        doctor integer,
+        air_date text,
+        story_code text,
+        part integer,
+        special integer not null default 0
+    );
+    create table if not exists catalog_meta(
+        key text primary key,
+        value text not null
+    );
+    ";
+    conn.execute_batch(create)?;
+    add_column(conn, "episodes", "era", "text not null default 'revival'")?;
+    add_column(conn, "episodes", "doctor", "integer")?;
+    add_column(conn, "episodes", "air_date", "text")?;
+    add_column(conn, "episodes", "story_code", "text")?;
+    add_column(conn, "episodes", "part", "integer")?;
+    add_column(conn, "episodes", "special", "integer not null default 0")?;
+    // File the episode was synced from, revival rows from before sources were tracked
+    // came from episodes.json
+    add_column(conn, "episodes", "source", "text")?;
+    conn.execute(
+        "update episodes set source = 'episodes.json' where source is null and era = 'revival'",
+        [],
+    )?;
+    // Catalogs built before the unique key may hold duplicate rows
+    let migrate = r"
+    delete from episodes where rowid not in (select min(rowid) from episodes group by id);
+    create unique index if not exists episodes_id on episodes(id);
+    ";
+    conn.execute_batch(migrate)?;
+    // Full-text index over the catalog, kept in step with the episodes table by triggers
+    let create_fts = r"
+    create virtual table if not exists episodes_fts using fts5(
+        title,
+        content='episodes',
+        content_rowid='rowid',
+        tokenize='unicode61 remove_diacritics 2'
+    );
+    create trigger if not exists episodes_fts_insert after insert on episodes begin
+        insert into episodes_fts(rowid, title) values (new.rowid, new.title);
+    end;
+    create trigger if not exists episodes_fts_delete after delete on episodes begin
+        insert into episodes_fts(episodes_fts, rowid, title) values ('delete', old.rowid, old.title);
+    end;
+    create trigger if not exists episodes_fts_update after update on episodes begin
+        insert into episodes_fts(episodes_fts, rowid, title) values ('delete', old.rowid, old.title);
+        insert into episodes_fts(rowid, title) values (new.rowid, new.title);
+    end;
+    insert into episodes_fts(episodes_fts) values ('rebuild');
+    ";
+    conn.execute_batch(create_fts)?;
+    // Provider responses keyed by catalog ID, and the episodes no online provider knows
+    let create_metadata = r"
+    create table if not exists title_metadata(
+        id text primary key,
+        release_year integer,
+        release_month integer,
+        release_day integer,
+        rating real,
+        votes integer,
+        plot text,
+        runtime integer,
+        image text,
+        fetched_at integer not null
+    );
+    create table if not exists title_metadata_missing(
+        id text primary key,
+        checked_at integer not null
+    );
+    ";
+    conn.execute_batch(create_metadata)?;
+    // Per-user recommendations, watchlists and watch history
+    let create_watch = r"
+    create table if not exists recommendations(
+        user text not null,
+        episode_id text not null,
+        recommended_at text not null,
+        primary key (user, episode_id)
+    );
+    create table if not exists watchlist(
+        user text not null,
+        episode_id text not null,
+        added_at text not null,
+        primary key (user, episode_id)
+    );
+    create table if not exists watch_history(
+        user text not null,
+        episode_id text not null,
+        watched_at text not null,
+        primary key (user, episode_id)
+    );
+    ";
+    conn.execute_batch(create_watch)?;
+    // Doctor pictures downloaded from the image search, files live in image_cache/
+    let create_image_cache = r"
+    create table if not exists image_cache(
+        key text primary key,
+        url text not null,
+        hash text not null,
+        file text not null,
+        content_type text not null,
+        size integer not null,
+        fetched_at integer not null,
+        last_used integer not null
+    )";
+    conn.execute(create_image_cache, ())?;
+    // Pictures moderators blocked with /blockimage, by content hash and source URL
+    let create_image_blacklist = r"
+    create table if not exists image_blacklist(
+        hash text primary key,
+        url text not null,
+        reason text not null,
+        blocked_by text not null,
+        blocked_at text not null
+    )";
+    conn.execute(create_image_blacklist, ())?;
+    // Quotes synced from quotes.json, the Doctor is an ID and the episode a catalog title
+    let quote_columns = r"(
+        id integer primary key,
+        quote text not null,
+        speaker text not null,
+        doctor text,
+        episode text,
+        tags text not null default '',
+        source text not null default 'file',
+        submitted_by text,
+        guild text
+    )";
+    conn.execute(
+        &format!("create table if not exists quotes{}", quote_columns),
+        (),
+    )?;
+    add_column(conn, "quotes", "submitted_by", "text")?;
+    // Guild an approved submission belongs to, quotes.json quotes have none
+    add_column(conn, "quotes", "guild", "text")?;
+    // Quotes members sent with /quotes add, pending until a moderator approves or rejects them
+    let create_quote_submissions = r"
+    create table if not exists quote_submissions(
+        id integer primary key,
+        guild text not null,
+        quote text not null,
+        speaker text not null,
+        episode text,
+        user text not null,
+        name text not null,
+        status text not null,
+        submitted_at text not null,
+        reviewed_by text,
+        reviewed_at text
+    )";
+    conn.execute(create_quote_submissions, ())?;
+    // Submissions approved before quotes had a guild go back to the guild they came from
+    conn.execute(
+        "update quotes set guild = (
+            select guild from quote_submissions s
+            where s.quote = quotes.quote and s.status = 'approved' order by s.id limit 1
+        )
+        where source = 'submitted' and guild is null",
+        [],
+    )?;
+    // Quotes used to be unique across guilds, they are copied into a table without the
+    // constraint, keeping their IDs. A quote is now unique per guild, shared ones count once.
+    let schema: String = conn.query_row(
+        "select sql from sqlite_master where type = 'table' and name = 'quotes'",
+        [],
+        |row| row.get(0),
+    )?;
+    if schema.contains("quote text not null unique") {
+        conn.execute_batch(&format!(
+            "begin;
+            create table quotes_scoped{};
+            insert into quotes_scoped (id, quote, speaker, doctor, episode, tags, source, submitted_by, guild)
+            select id, quote, speaker, doctor, episode, tags, source, submitted_by, guild from quotes;
+            drop table quotes;
+            alter table quotes_scoped rename to quotes;
+            commit;",
+            quote_columns
+        ))?;
+    }
+    conn.execute(
+        "create unique index if not exists quotes_guild_quote on quotes(quote, ifnull(guild, ''))",
+        [],
+    )?;
+    // Full-text index over quotes for /quote search, kept in step by triggers
+    let create_quotes_fts = r"
+    create virtual table if not exists quotes_fts using fts5(
+        quote,
+        speaker,
+        episode,
+        tags,
+        content='quotes',
+        content_rowid='id',
+        tokenize='unicode61 remove_diacritics 2'
+    );
+    create trigger if not exists quotes_fts_insert after insert on quotes begin
+        insert into quotes_fts(rowid, quote, speaker, episode, tags)
+        values (new.id, new.quote, new.speaker, new.episode, new.tags);
+    end;
+    create trigger if not exists quotes_fts_delete after delete on quotes begin
+        insert into quotes_fts(quotes_fts, rowid, quote, speaker, episode, tags)
+        values ('delete', old.id, old.quote, old.speaker, old.episode, old.tags);
+    end;
+    create trigger if not exists quotes_fts_update after update on quotes begin
+        insert into quotes_fts(quotes_fts, rowid, quote, speaker, episode, tags)
+        values ('delete', old.id, old.quote, old.speaker, old.episode, old.tags);
+        insert into quotes_fts(rowid, quote, speaker, episode, tags)
+        values (new.id, new.quote, new.speaker, new.episode, new.tags);
+    end;
+    insert into quotes_fts(quotes_fts) values ('rebuild');
+    ";
+    conn.execute_batch(create_quotes_fts)?;
+    // Per guild quote of the day, the time is HH:MM in UTC
+    let create_quote_schedules = r"
+    create table if not exists quote_schedules(
+        guild text primary key,
+        channel text not null,
+        time text not null,
+        last_posted text
+    );
+    create table if not exists quote_history(
+        guild text not null,
+        quote_id integer not null,
+        posted_at text not null,
+        primary key (guild, quote_id)
+    );
+    ";
+    conn.execute_batch(create_quote_schedules)?;
+    Ok(())
 }
 
 fn prepare_points_database() {
@@ -367,6 +1530,150 @@ fn prepare_points_database() {
             println!("Couldn't create table: {}", e);
         }
     }
+    // Tournament matches are scored apart from the leaderboard
+    let create_tournaments = r"
+    create table if not exists tournaments(
+        channel text primary key,
+        guild text not null,
+        host text not null,
+        rounds integer not null,
+        round integer not null,
+        state text not null,
+        category text not null default 'general',
+        question text
+    );
+    create table if not exists tournament_players(
+        channel text not null,
+        player text not null,
+        name text not null,
+        score integer not null,
+        contender integer not null,
+        attempted text,
+        primary key (channel, player)
+    );
+    ";
+    match conn.execute_batch(create_tournaments) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Couldn't create tournament tables: {}", e);
+        }
+    }
+    match add_column(
+        &conn,
+        "tournaments",
+        "category",
+        "text not null default 'general'",
+    ) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Couldn't add tournament categories: {}", e);
+        }
+    }
+    match add_column(&conn, "tournaments", "question", "text") {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Couldn't add tournament questions: {}", e);
+        }
+    }
+    match add_column(&conn, "tournament_players", "attempted", "text") {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Couldn't add tournament attempts: {}", e);
+        }
+    }
+    // Team scores are kept per season next to the individual points
+    let create_teams = r"
+    create table if not exists teams(
+        guild text not null,
+        name text not null,
+        role text,
+        primary key (guild, name)
+    );
+    create table if not exists team_members(
+        guild text not null,
+        user text not null,
+        team text not null,
+        primary key (guild, user)
+    );
+    create table if not exists team_points(
+        guild text not null,
+        team text not null,
+        season integer not null,
+        score integer not null,
+        primary key (guild, team, season)
+    );
+    create table if not exists team_seasons(
+        guild text primary key,
+        season integer not null
+    );
+    ";
+    match conn.execute_batch(create_teams) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Couldn't create team tables: {}", e);
+        }
+    }
+    // Daily challenge answers and streaks
+    let create_daily = r"
+    create table if not exists daily_questions(
+        day text primary key,
+        question text not null,
+        answer text not null
+    );
+    create table if not exists daily_answers(
+        guild text not null,
+        user text not null,
+        day text not null,
+        correct integer not null,
+        primary key (guild, user, day)
+    );
+    create table if not exists daily_streaks(
+        guild text not null,
+        user text not null,
+        streak integer not null,
+        best integer not null,
+        last_day text not null,
+        primary key (guild, user)
+    );
+    create table if not exists daily_summaries(
+        guild text not null,
+        day text not null,
+        primary key (guild, day)
+    );
+    ";
+    match conn.execute_batch(create_daily) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Couldn't create daily tables: {}", e);
+        }
+    }
+    // The category of the questions regular trivia rounds ask in each guild
+    let create_trivia_settings = r"
+    create table if not exists trivia_settings(
+        guild text primary key,
+        category text not null
+    );
+    ";
+    match conn.execute(create_trivia_settings, ()) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Couldn't create trivia settings table: {}", e);
+        }
+    }
+    // Direct message practice scores, never mixed into guild points
+    let create_practice = r"
+    create table if not exists practice_points(
+        user text primary key,
+        score integer not null,
+        answered integer not null
+    );
+    ";
+    match conn.execute(create_practice, ()) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Couldn't create practice table: {}", e);
+        }
+    }
 }
 
 #[tokio::main]
@@ -375,62 +1682,132 @@ async fn main() {
 
     // Check command line arguments
     let args = Args::parse();
-    if let Some(subcmd) = args.command {
+    if let Some(subcmd) = &args.command {
         match subcmd {
             Subcmd::HelpBot => {
                 println!("K9 is the Discord Bot I've made for the Rust course project. The commands it implements are as follows:
-                - /quote: sends a random quote from the Doctor Who series
-                - /doctor n: sends a picture of the n-th doctor
+                - /quote [doctor] [speaker] [keyword] [search]: sends a random quote from the Doctor Who series, optionally filtered, or lists every quote matching the search page by page
+                - /quotes add: submits a quote, moderators (Manage Messages) review submissions with /quotes queue, approved quotes are credited to their submitter and only shown in this server
+                - /quoteoftheday set|off: posts a quote every day at a UTC time in a channel, without repeats until every quote was posted (Manage Server)
+                - /reload: reloads questions.json and quotes.json, keeping the old content if a file is invalid (Administrator)
+                - /doctor name: shows the profile of a doctor (e.g. 10, war, fugitive), with buttons to step through the Doctors
                 - /episode name: searches for a specific episode
+                - /season n, /episodes doctor:n, /onthisday: browse the episode catalog
+                - /watch: recommends a random episode, with a reroll and a mark watched button
+                - /watchlist add|remove|list: keeps a list of episodes you want to watch
+                - /watched add|remove|progress: tracks watched episodes and compares progress with a friend
+                - /blockimage doctor [reason]: moderators block the cached search picture of a doctor
                 - /points: shows the number of points user have on this guild
-Users can also answer trivia questions by replying to the bot's messages with the correct answer. The bot will then update the user's points and send another trivia question.");
+                - /trivia [category]: shows or sets whether trivia asks general, who said it or mixed questions (Manage Server)
+                - /daily: answers the daily challenge question privately and keeps a daily streak
+                - /team create|join|leave|leaderboard|reset: plays trivia as a team, with seasons of team scores
+                - /tournament start|join|begin|skip|cancel: runs a fixed-length trivia match with its own scoring, one answer per player and question, with general, quotes (who said it?) or mixed questions
+Users can also answer trivia questions by replying to the bot's messages with the correct answer. The bot will then update the user's points and send another trivia question.
+Sending the bot a direct message starts a solo practice mode with a personal score that never counts towards guild leaderboards, send `who said it` there to practice on quotes.");
             }
             Subcmd::Verify => {
                 let integrity = verify_integrity();
                 if integrity == 0 {
                     println!("✅Everything is in order");
                 } else {
-                    println!("❌{} problems found. Please fix them before starting the app", integrity);
+                    println!(
+                        "❌{} problems found. Please fix them before starting the app",
+                        integrity
+                    );
                     return;
                 }
             }
+            Subcmd::Prefetch { .. } | Subcmd::Import { .. } | Subcmd::WarmCache { .. } => {}
         }
     }
 
+    // HTTP client for the external APIs, shared with the handlers through ctx.data
+    let http = match HttpClient::new() {
+        Ok(body) => body,
+        Err(e) => {
+            println!("Couldn't create HTTP client: {}", e);
+            return;
+        }
+    };
+
     // Prepare Episodes Database
-    let conn = match Connection::open("episodes.db") {
+    let mut conn = match Connection::open("episodes.db") {
         Ok(body) => body,
         Err(e) => {
             println!("Couldn't connect to episodes database {}", e);
             return;
         }
     };
-    match conn.execute("drop table if exists episodes", []) {
+    match prepare_episodes_database(&conn) {
         Ok(_) => {}
         Err(e) => {
-            println!("Couldn't drop table: {}", e);
+            println!("Couldn't create table: {}", e);
             return;
         }
     }
-    let create = r"
-    create table if not exists episodes(
-        id text not null,
-        title text not null,
-        season integer not null,
-        episode integer not null
-    );
-    ";
-    match conn.execute(create, ()) {
+    match populate_database(&mut conn) {
         Ok(_) => {}
         Err(e) => {
-            println!("Couldn't create table: {}", e);
+            println!("Couldn't sync episodes.json: {}", e);
+        }
+    }
+    // Trivia questions and quotes are kept in memory, quotes are also synced into the database
+    for line in reload_content() {
+        println!("{}", line);
+    }
+    // Classic serials and specials come from a curated CSV
+    if fs::metadata(CLASSIC_CSV).is_ok() {
+        match import_classic_csv(&mut conn, CLASSIC_CSV, false) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Couldn't import {}: {}", CLASSIC_CSV, e);
+            }
+        }
+    }
+    if let Some(Subcmd::Import { path }) = &args.command {
+        match import_classic_csv(&mut conn, path, true) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("❌Couldn't import {}: {}", path, e);
+            }
+        }
+        return;
+    }
+    match backfill_catalog(&conn) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Couldn't backfill episode catalog: {}", e);
         }
     }
-    populate_database(&conn);
+
+    // Fill the image cache and exit
+    if let Some(Subcmd::WarmCache { force }) = args.command {
+        match warm_image_cache(&http, force).await {
+            Ok(_) => {}
+            Err(e) => {
+                println!("❌Failed to warm the image cache: {}", e);
+            }
+        }
+        return;
+    }
+
+    // Fill the metadata cache and exit
+    if let Some(Subcmd::Prefetch { force }) = args.command {
+        match prefetch_metadata(&conn, &http, force).await {
+            Ok(_) => {}
+            Err(e) => {
+                println!("❌Failed to prefetch metadata: {}", e);
+            }
+        }
+        return;
+    }
 
     //Prepare Points Database
     prepare_points_database();
 
+    // Edited content files are picked up without a restart
+    tokio::spawn(watch_content());
+
     // Token to connecting to Discord API
     let token: String =
         env::var("DISCORD_TOKEN").expect("discord bot token should be in .env, can't start bot");
@@ -444,6 +1821,7 @@ Users can also answer trivia questions by replying to the bot's messages with th
 
     let mut client: Client = match Client::builder(&token, intents)
         .event_handler(Handler)
+        .type_map_insert::<HttpClient>(http)
         .await
     {
         Ok(body) => body,